@@ -1,7 +1,6 @@
 extern crate lichen;
 
 use std::io;
-use std::collections::HashMap;
 
 use lichen::parse::Parser;
 use lichen::var::Var;
@@ -40,23 +39,20 @@ fn main() {
                         Err(_) => panic!()
                     }
                 },
-                Next::Select(selects) => {
+                Next::Select(_) => {
                     println!("\nEnter in a destination");
 
-                    // we're going to invert K/V for convenience for input
-                    let mut choices: HashMap<String,String> = HashMap::new();
-                    for (key,val) in selects.iter() {
-                        println!("{:}, type {:?}", key, val[0].to_string());
-                        choices.insert(val[0].to_string(),key.clone());
+                    // choices are listed in source order
+                    for (i,choice) in ev.choices().iter().enumerate() {
+                        println!("{:}, type {:?}", choice.text, i);
                     }
                     
                     let mut line = String::new();
                     
                     match io::stdin().read_line(&mut line) {
                         Ok(_) => {
-                            let line = line.trim();
-                            if let Some(_) = choices.remove(line) {
-                                ev.advance(line.to_owned());
+                            if let Ok(idx) = line.trim().parse::<usize>() {
+                                let _ = ev.choose(idx); // invalid picks continue on
                             }
                         },
                         Err(_) => panic!()
@@ -0,0 +1,166 @@
+use std::collections::{HashMap,BTreeSet};
+use std::cmp::Reverse;
+
+use var::Var;
+use def::{Def,DefBlock};
+use env::Env;
+use source::Src;
+use logic::{Logic,Logics};
+use session::Session;
+
+/// Name of the def block facts are queried through, eg: fact.who
+pub const FACTS: &str = "fact";
+
+/// A compiled bark rule
+struct Rule {
+    node: String,
+    /// Criteria names, in source order
+    criteria: Vec<String>,
+    logic: Logics,
+    cooldown: u64,
+}
+
+/// Bark rule database, compiled from nodes marked with [bark]
+///
+/// A query passes in facts, readable from the rules as fact.name,
+/// and picks the most specific rule whose criteria all pass.
+/// Specificity is the number of criteria, ties are picked at random
+pub struct Barks {
+    /// Ordered by specificity, most specific first
+    rules: Vec<Rule>,
+    /// Rules keyed by a fact they require to equal a value, eg: fact.who == guard
+    index: HashMap<(String,String),Vec<usize>>,
+    /// Rules without any such fact, always checked
+    unindexed: Vec<usize>,
+    /// Time each rule last matched
+    fired: HashMap<usize,u64>,
+}
+
+impl Barks {
+    /// Compiles the bark rules in env
+    pub fn compile (env: &Env) -> Barks {
+        let mut names: Vec<&String> = env.src.keys().collect();
+        names.sort();
+
+        let mut rules = vec![];
+        for name in names {
+            let b = &env.src[name];
+            let bark = match b.bark {
+                Some(bark) => bark,
+                None => continue,
+            };
+
+            let mut rule = Rule { node: name.clone(), criteria: vec![], logic: Logics::new(), cooldown: bark.cooldown };
+            for src in b.src.iter() {
+                match *src {
+                    Src::Logic(ref name, ref logic) => {
+                        rule.criteria.push(name.clone());
+                        rule.logic.insert(name.clone(), logic.eval());
+                    },
+                    _ => break, // NOTE: only leading logic are criteria
+                }
+            }
+
+            rules.push((Barks::key(b.src.iter()), rule));
+        }
+
+        // NOTE: stable, so equal specificity keeps node order
+        rules.sort_by_key(|r| Reverse(r.1.criteria.len()));
+
+        let mut index: HashMap<(String,String),Vec<usize>> = HashMap::new();
+        let mut unindexed = vec![];
+        let rules = rules.into_iter().enumerate().map(|(i, (key, rule))| {
+            match key {
+                Some(key) => index.entry(key).or_default().push(i),
+                None => unindexed.push(i),
+            }
+
+            rule
+        }).collect();
+
+        Barks { rules, index, unindexed, fired: HashMap::new() }
+    }
+
+    /// Finds the first criteria requiring a fact to equal a plain value
+    fn key<'a, I: Iterator<Item=&'a Src>> (srcs: I) -> Option<(String,String)> {
+        for src in srcs {
+            match *src {
+                Src::Logic(_, Logic::EQ(ref left, ref right)) => {
+                    let fact = |v: &Var| match *v {
+                        Var::Sym(ref s) => s.strip_prefix(FACTS).and_then(|s| s.strip_prefix('.')).map(|s| s.to_owned()),
+                        _ => None,
+                    };
+                    // NOTE: symbols with a path may resolve to def vars
+                    let plain = |v: &Var| match *v {
+                        Var::Sym(ref s) => !s.contains('.'),
+                        Var::Map(_) | Var::Nil => false,
+                        _ => true,
+                    };
+
+                    match (fact(left), fact(right)) {
+                        (Some(f), None) if plain(right) => return Some((f, right.to_string())),
+                        (None, Some(f)) if plain(left) => return Some((f, left.to_string())),
+                        _ => {},
+                    }
+                },
+                Src::Logic(_,_) => {},
+                _ => break,
+            }
+        }
+
+        None
+    }
+
+    /// Number of rules compiled
+    pub fn len (&self) -> usize {
+        self.rules.len()
+    }
+
+    pub fn is_empty (&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    /// Picks the best matching rule's node, marking the rule as fired at the time given
+    ///
+    /// Rules still cooling down are skipped. Facts are set as the 'fact' def block
+    /// for the query, any existing block of the same name is put back afterwards
+    pub fn query (&mut self,
+                  facts: &HashMap<String,Var>,
+                  def: &mut Def,
+                  session: &Session,
+                  now: u64) -> Option<String> {
+        let mut candidates: BTreeSet<usize> = self.unindexed.iter().cloned().collect();
+        for (k, v) in facts.iter() {
+            if let Some(rules) = self.index.get(&(k.clone(), v.to_string())) {
+                candidates.extend(rules.iter().cloned());
+            }
+        }
+
+        let block = DefBlock { name: FACTS.to_owned(), data: facts.clone() };
+        let prev = def.insert(FACTS.to_owned(), block);
+
+        let mut matched: Vec<usize> = vec![];
+        for i in candidates {
+            let rule = &self.rules[i];
+            if let Some(&first) = matched.first() {
+                if rule.criteria.len() < self.rules[first].criteria.len() { break }
+            }
+            if self.fired.get(&i).map_or(false, |&t| now < t.saturating_add(rule.cooldown)) { continue }
+
+            let passes = rule.criteria.iter().all(|name| {
+                rule.logic[name].run(def, &rule.logic, session) == Some(true)
+            });
+            if passes { matched.push(i); }
+        }
+
+        match prev {
+            Some(prev) => { def.insert(FACTS.to_owned(), prev); },
+            None => { def.remove(FACTS); },
+        }
+
+        if matched.is_empty() { return None }
+        let i = matched[session.rng.below(matched.len() as u64) as usize];
+        self.fired.insert(i, now);
+        Some(self.rules[i].node.clone())
+    }
+}
@@ -0,0 +1,97 @@
+use std::collections::HashMap;
+
+use var::Var;
+use source::Src;
+use env::Env;
+
+/// Command for the host to run, eg: do play_sound "door.ogg" 0.5
+///
+/// Args are resolved and formatted when the command is reached
+#[derive(Debug,PartialEq,Clone)]
+pub struct Command {
+    pub name: String,
+    pub args: Vec<Var>,
+}
+
+/// Number of args a command takes
+#[derive(Debug,PartialEq,Clone,Copy)]
+pub enum Arity {
+    Exact(usize),
+    /// Inclusive range
+    Range(usize,usize),
+    AtLeast(usize),
+}
+
+impl Arity {
+    pub fn allows (&self, n: usize) -> bool {
+        match *self {
+            Arity::Exact(a) => n == a,
+            Arity::Range(min, max) => min <= n && n <= max,
+            Arity::AtLeast(min) => n >= min,
+        }
+    }
+}
+
+/// Command found in source which the host can't run
+#[derive(Debug,PartialEq,Clone)]
+pub struct CommandError {
+    pub node: String,
+    /// Line number the statement starts on
+    pub line: usize,
+    pub name: String,
+    pub reason: &'static str,
+}
+
+/// Host command registry, for checking commands in source at load time
+#[derive(Debug,PartialEq,Clone,Default)]
+pub struct Commands(HashMap<String,Arity>);
+
+impl Commands {
+    pub fn new () -> Commands {
+        Commands::default()
+    }
+
+    /// Registers a command, replacing the arity of any with the same name
+    pub fn register (&mut self, name: &str, arity: Arity) -> Option<Arity> {
+        self.0.insert(name.to_owned(), arity)
+    }
+
+    pub fn get (&self, name: &str) -> Option<Arity> {
+        self.0.get(name).cloned()
+    }
+
+    /// Checks a single command against the registry
+    pub fn check (&self, name: &str, args: usize) -> Result<(),&'static str> {
+        match self.0.get(name) {
+            None => Err("Unknown command"),
+            Some(arity) if !arity.allows(args) => Err("Wrong number of command args"),
+            _ => Ok(()),
+        }
+    }
+
+    /// Checks every command statement in env, ordered by node name and line
+    pub fn validate (&self, env: &Env) -> Result<(),Vec<CommandError>> {
+        let mut names: Vec<&String> = env.src.keys().collect();
+        names.sort();
+
+        let mut errors = vec![];
+        for name in names {
+            let b = &env.src[name];
+            for (i, src) in b.src.iter().enumerate() {
+                if let Src::Command(ref cmd, ref args) = *src {
+                    if let Err(reason) = self.check(cmd, args.len()) {
+                        errors.push(CommandError {
+                            node: name.clone(),
+                            line: b.meta.get(i).map_or(0, |m| m.line),
+                            name: cmd.clone(),
+                            reason,
+                        });
+                    }
+                }
+            }
+        }
+
+        if errors.is_empty() { Ok(()) }
+        else { Err(errors) }
+    }
+}
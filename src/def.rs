@@ -20,15 +20,99 @@ impl DefBlock {
             data: HashMap::new()
         }
     }
+
+    /// Follows nested keys through any Map vars in this block
+    ///
+    /// Eg: ['members','0','hp'] looks up the 'hp' key of map '0' in map 'members'
+    pub fn get_path (&self, keys: &[&str]) -> Option<&Var> {
+        if let Some((first, rest)) = keys.split_first() {
+            let mut var = self.data.get(*first)?;
+            for key in rest {
+                match *var {
+                    Var::Map(ref map) => { var = map.get(*key)?; },
+                    _ => return None
+                }
+            }
+
+            return Some(var)
+        }
+
+        None
+    }
+
+    /// Sets a nested key, building any missing Maps along the way
+    ///
+    /// Non-Map vars found mid-path are replaced with a new Map
+    pub fn set_path (&mut self, keys: &[&str], var: Var) {
+        if let Some((last, path)) = keys.split_last() {
+            let mut map = &mut self.data;
+            for key in path {
+                let entry = map.entry((*key).to_owned())
+                    .or_insert_with(|| Var::Map(HashMap::new()));
+                if let Var::Map(_) = *entry {}
+                else { *entry = Var::Map(HashMap::new()); }
+
+                map = match *entry {
+                    Var::Map(ref mut m) => m,
+                    _ => unreachable!(),
+                };
+            }
+
+            map.insert((*last).to_owned(), var);
+        }
+    }
+
+
+    /// Removes a nested key, returning the var that was removed
+    pub fn remove_path (&mut self, keys: &[&str]) -> Option<Var> {
+        if let Some((last, path)) = keys.split_last() {
+            let mut map = &mut self.data;
+            for key in path {
+                map = match map.get_mut(*key) {
+                    Some(&mut Var::Map(ref mut m)) => m,
+                    _ => return None,
+                };
+            }
+
+            return map.remove(*last)
+        }
+
+        None
+    }
+}
+
+/// Finds the def block that a full path points into
+///
+/// Returns the number of path segments naming the block.
+/// Blocks named with dots (eg: 'player.dagger') are preferred when present,
+/// otherwise the first segment is the block and the rest are nested keys
+pub fn find_block (def: &Def, keys: &[&str]) -> Option<usize> {
+    for n in (1..keys.len()).rev() {
+        if def.contains_key(&keys[..n].join(".")) {
+            return Some(n)
+        }
+    }
+
+    None
+}
+
+/// Resolves a full path, eg: 'party.members.0.hp', into its var
+pub fn lookup<'d> (def: &'d Def, keys: &[&str]) -> Option<&'d Var> {
+    if let Some(n) = find_block(def, keys) {
+        if let Some(b) = def.get(&keys[..n].join(".")) {
+            return b.get_path(&keys[n..])
+        }
+    }
+
+    None
 }
 
 impl Eval for Def {
     fn get (&self, path: Option<Vec<&str>>, lookup: &str) -> Option<Var> {
-        if let Some(path) = path {
-            if let Some(ref def) = self.get(path[0]) {
-                if let Some(v) = def.data.get(lookup) {
-                    return Some(v.clone())
-                }
+        if let Some(mut path) = path {
+            path.push(lookup);
+            if let Some(v) = ::def::lookup(self, &path) {
+                return Some(v.clone())
             }
         }
 
@@ -40,43 +124,25 @@ impl Eval for Def {
         let mut resolved = None;
 
         loop { // resolve symbol references
-            let (path,sym) = self.as_path(lookup);
-            if let Some(mut path) = path {
-                // NOTE: for now we are using nested paths as actual names
-                // so we need to rebuild it as a full name if necessary
-                let mut p = String::new();
-                if path.len() > 1 {
-                    p.push_str(path.remove(0));
-                    p.push('.');
-                    p.push_str(path.remove(0));
-                }
-
-                let path_final = {
-                    if p.is_empty() { path[0] }
-                    else { &p }
-                };
-                
-                if let Some(ref def) = self.get(path_final) {
-                    if let Some(v) = def.data.get(sym) {
-                        match v {
-                            &Var::Sym(ref sym) => {
-                                if lookup != sym {
-                                    resolved = Some(v.clone()); // take note that we resolved atleast once
-                                    lookup = sym;
-                                    continue
-                                }
-                                else {
-                                    return Some((v.clone(), false))
-                                }
-                            },
-                            _ => {
-                                return Some((v.clone(), true))
-                            }
+            let keys: Vec<&str> = lookup.split_terminator('.').collect();
+            if keys.len() < 2 { break }
+
+            if let Some(v) = ::def::lookup(self, &keys) {
+                match *v {
+                    Var::Sym(ref sym) => {
+                        if lookup != sym {
+                            resolved = Some(v.clone()); // take note that we resolved atleast once
+                            lookup = sym;
+                            continue
+                        }
+                        else {
+                            return Some((v.clone(), false))
                         }
+                    },
+                    _ => {
+                        return Some((v.clone(), true))
                     }
-                    else { break }
                 }
-                else { break }
             }
             else { break }
         }
@@ -86,52 +152,45 @@ impl Eval for Def {
         None
     }
 
-    #[allow(unused_variables)]
     fn set (&mut self, path: Option<Vec<&str>>, lookup: &str, var: Var) {
         if let Some(mut path) = path {
-            // NOTE: for now we are using nested paths as actual names
-            // so we need to rebuild it as a full name if necessary
-            let mut p = String::new();
-            let mut block_name = String::new();
-            if path.len() > 1 {
-                p.push_str(path.remove(0));
-                p.push('.');
-                let n = path.remove(0);
-                block_name.push_str(&n);
-                p.push_str(n);
-            }
+            path.push(lookup);
 
-            let path_final = {
-                if p.is_empty() { path[0] }
-                else { &p }
-            };
-            
-            if let Some(ref mut def) = self.get_mut(path_final) {
-                let set;
-                if let Some(v) = def.data.get_mut(lookup) {
-                    *v = var;
-                    set = None;
+            // write into an existing block if we can find one
+            if let Some(n) = find_block(self, &path) {
+                if let Some(b) = self.get_mut(&path[..n].join(".")) {
+                    b.set_path(&path[n..], var);
+                    return
                 }
-                else { set = Some(var); }
-                
-                if let Some(var) = set { // NOTE: we're building this from scratch, this should be considered explicit instead
-                    def.data.insert(lookup.to_owned(), var);
-                }
-
-                return
             }
             
-            // if we didn't successfully insert, let's build from scratch the new block path
-            let mut map = HashMap::new();
-            map.insert(lookup.to_owned(), var);
+            // otherwise build from scratch the new block, nesting the rest of the path
+            let mut def = DefBlock::new(path[0]);
+            def.set_path(&path[1..], var);
             
-            let def = DefBlock {
-                name: block_name,
-                data: map,
-            };
+            self.insert(path[0].to_owned(), def);
+        }
+    }
+
+    fn unset (&mut self, path: Option<Vec<&str>>, lookup: &str) -> Option<Var> {
+        if let Some(mut path) = path {
+            path.push(lookup);
             
-            self.insert(path_final.to_owned(), def);
+            if let Some(n) = find_block(self, &path) {
+                if let Some(b) = self.get_mut(&path[..n].join(".")) {
+                    return b.remove_path(&path[n..])
+                }
+            }
         }
+
+        None
+    }
+
+    fn delete (&mut self, lookup: &str) -> bool {
+        if self.remove(lookup).is_some() { return true }
+
+        // nested maps are treated as blocks of their own
+        self.unset_path(lookup).is_some()
     }
 
     #[allow(unused_variables)]
@@ -1,23 +1,74 @@
 use std::collections::HashMap;
 
-use def::Def;
+use def::{self,Def};
 use parse::{Block,SrcBlock};
 use var::Var;
 use fun::Fun;
+use interp::PluralRules;
+use locale::{Locale,Line};
+use command::{Commands,CommandError};
 
 impl Env {
     pub fn def_contains(def: &Def, path: Option<Vec<&str>>, lookup: &str) -> bool {
-        if let Some(path) = path {
-            if let Some(ref def) = def.get(path[0]) {
-                return def.data.contains_key(lookup)
-            }
+        if let Some(mut path) = path {
+            path.push(lookup);
+            return def::lookup(def, &path).is_some()
         }
 
         false
     }
 
     pub fn empty () -> Env {
-        Env { src: HashMap::new(), def: HashMap::new(), fun: HashMap::new() }
+        let mut plurals = HashMap::new();
+        plurals.insert("en".to_owned(), PluralRules::english());
+        
+        Env { src: HashMap::new(), def: HashMap::new(), fun: HashMap::new(),
+              locale: "en".to_owned(), plurals, locales: HashMap::new(),
+              commands: Commands::new() }
+    }
+
+    /// Checks command statements against the registered commands
+    ///
+    /// Call once commands are registered and source is loaded
+    pub fn validate_commands (&self) -> Result<(),Vec<CommandError>> {
+        self.commands.validate(self)
+    }
+
+    /// Lists every translatable string with its line ID, ordered by node name
+    pub fn lines (&self) -> Vec<Line> {
+        let mut names: Vec<&String> = self.src.keys().collect();
+        names.sort();
+        
+        let mut lines = vec![];
+        for name in names {
+            lines.extend(Line::collect(&self.src[name]));
+        }
+
+        lines
+    }
+
+    /// Names of nodes carrying a tag, sorted, see parse::has_tag
+    pub fn tagged (&self, tag: &str) -> Vec<&str> {
+        let mut names: Vec<&str> = self.src.values()
+            .filter(|b| b.has_tag(tag))
+            .map(|b| &b.name[..]).collect();
+        names.sort();
+        names
+    }
+
+    /// Tags on a node, None when there is no such node
+    pub fn node_tags (&self, node: &str) -> Option<&[String]> {
+        self.src.get(node).map(|b| &b.tags[..])
+    }
+
+    /// Adds a locale string table, replacing any with the same name
+    pub fn insert_locale (&mut self, locale: Locale) -> Option<Locale> {
+        self.locales.insert(locale.name.clone(), locale)
+    }
+
+    /// Plural rules for the current locale, if any were set
+    pub fn plural_rules (&self) -> Option<&PluralRules> {
+        self.plurals.get(&self.locale)
     }
 
     pub fn insert (&mut self, mut v: Vec<Block>) {
@@ -47,4 +98,13 @@ pub struct Env {
     pub def: Def,
     pub src: HashMap<String, SrcBlock>,
     pub fun: HashMap<String, Fun>,
+
+    /// Current locale, 'en' by default
+    pub locale: String,
+    /// Plural rules keyed by locale, used when formatting plural forms
+    pub plurals: HashMap<String, PluralRules>,
+    /// String tables keyed by locale, source text is used when a line is missing
+    pub locales: HashMap<String, Locale>,
+    /// Commands the host can run, see validate_commands
+    pub commands: Commands,
 }
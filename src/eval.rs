@@ -1,10 +1,21 @@
 use env::Env;
-use var::Var;
-use source::{Src,Next};
+use var::{Var,Float};
+use source::{Src,Next,Choice,Select};
 use logic::LogicFn;
-use def::DefBlock;
+use def::{Def,DefBlock};
+use interp::Interp;
+use locale::Line;
+use parse::{SrcBlock,Guard};
+use event::Event;
+use session::Session;
+use rng::{Rng,Random,RngCell};
+use input::Input;
+use command::Command;
+use bark::Barks;
 
-use std::collections::HashMap;
+use std::collections::{HashMap,VecDeque};
+use std::cmp::Ordering;
+use std::mem;
 
 /// Creates a possible path from a dot-seperated string
 ///
@@ -48,6 +59,17 @@ pub trait Eval {
         self.set(path,lookup, v);
     }
 
+    /// Removes a var from the underlying mem/store, returning it if found
+    fn unset (&mut self, path: Option<Vec<&str>>, lookup: &str) -> Option<Var>;
+
+    fn unset_path (&mut self, lookup: &str) -> Option<Var> {
+        let (path,lookup) = self.as_path(lookup);
+        self.unset(path,lookup)
+    }
+
+    /// Removes a whole block of vars, returns true if it existed
+    fn delete (&mut self, lookup: &str) -> bool;
+
     /// A custom callable function
     ///
     /// Var represents variable to mutate
@@ -57,62 +79,553 @@ pub trait Eval {
     fn call (&mut self, var: Var, fun: &str, vars: &Vec<Var>) -> Option<Var>;
 }
 
+/// Name of the thread evaluation starts on
+pub const MAIN: &str = "main";
+
+/// A story thread, running alongside the main thread
+#[derive(Clone,Debug,PartialEq)]
+pub struct Thread {
+    pub name: String,
+    pub stack: Vec<String>,
+    /// Choices, input and timeout the thread is held on
+    held: Held,
+    /// Statement index and or-state of each node the thread is partway through
+    places: HashMap<String,(usize,bool)>,
+}
+
+impl Thread {
+    fn new (name: &str, node: &str) -> Thread {
+        Thread {
+            name: name.to_owned(),
+            stack: vec![node.to_owned()],
+            held: Held::default(),
+            places: HashMap::new(),
+        }
+    }
+}
+
+/// Order threads are stepped in
+#[derive(Clone,Copy,Debug,PartialEq,Default)]
+pub enum Schedule {
+    /// Other threads only step once the main thread has nothing left to run
+    #[default]
+    MainFirst,
+    /// Each step goes to the next thread in turn, main thread first
+    RoundRobin,
+}
+
 pub struct Evaluator<'e> {
     env: &'e mut Env,
+    /// Node stack of the thread stepping, the main thread otherwise
     node_stack: Vec<String>,
+    /// Other threads, in the order they were spawned
+    threads: Vec<Thread>,
+    /// Name of the thread stepping
+    thread: String,
+    schedule: Schedule,
+    /// Next thread to step, when round-robin
+    rr: usize,
+
+    /// Line IDs for the last emitted vars
+    line_ids: Vec<Option<String>>,
+    /// Command from the last statement run
+    command: Option<Command>,
+
+    /// Events from the step running, see next_event
+    events: VecDeque<Event>,
+    /// Pending typed events, along with their thread
+    queue: VecDeque<(String,Event)>,
+    /// Node and statement index last run
+    last: (String, usize),
+    finished: bool,
+
+    /// Held state of the thread stepping, the main thread otherwise
+    held: Held,
+    /// Ticks left for each waiting thread
+    waits: HashMap<String,u64>,
+    /// Turns, visits and chosen entries
+    session: Session,
+}
+
+/// Choices, input and timeout a thread is held on, kept until the thread steps on
+#[derive(Clone,Debug,PartialEq,Default)]
+struct Held {
+    /// Choices from the last select, until advanced or stepped past
+    choices: Vec<Choice>,
+    /// Select the choices came from
+    pending: Option<Pending>,
+    /// Input awaiting submission
+    prompt: Option<Prompt>,
+    /// Timed select or await, running out as the host ticks
+    timer: Option<Timer>,
+    /// Event and next the select or await yielded, yielded again after an interrupt
+    step: Option<(Event, Next)>,
+    /// Held state put aside by signals, innermost last
+    interrupts: Vec<Interrupt>,
+}
+
+/// Held state put aside while a signal's handlers run
+#[derive(Clone,Debug,PartialEq)]
+struct Interrupt {
+    /// Node stack the handlers were pushed onto, restored once it's back to this
+    stack: Vec<String>,
+    held: Held,
+}
+
+/// Select statement awaiting a choice
+#[derive(Clone,Debug,PartialEq)]
+struct Pending {
+    node: String,
+    stmt: usize,
+    /// Map entry index of each listed choice
+    entries: Vec<usize>,
+}
+
+/// Input statement awaiting a valid submission
+#[derive(Clone,Debug,PartialEq)]
+struct Prompt {
+    node: String,
+    stmt: usize,
+    input: Input,
+    /// Why the last submission failed
+    error: Option<String>,
+    /// Asked for since the last submission, stepping stops until it's submitted
+    asked: bool,
+}
+
+/// Select or await heading to a node once its ticks run out
+#[derive(Clone,Debug,PartialEq)]
+struct Timer {
+    node: String,
+    stmt: usize,
+    left: u64,
+    target: String,
 }
 
 impl<'e> Iterator for Evaluator<'e> {
         
         type Item = (Vec<Var>, Option<Next>); //here we only return node name as an option to advance
         fn next(&mut self) -> Option<Self::Item> {
-            if let Some(nn) = self.node_stack.pop() {
-                if let Some(r) = self.run(&nn) {
-                    // reset node if necessary
-                    if let Some(ref next) = r.1 {
-                        match next {
-                            &Next::Restart(ref nn) => {
-                                if let &Some(ref nn) = nn {
-                                    if let Some(b) = self.env.src.get_mut(nn) {
-                                        b.idx = 0;
-                                    }
-                                }
-                            }
-                            _ => {}, // we handle the rest during Run, for convenience
-                        }
-                    }
-                    
-                    Some(r)
-                }
-                else { self.next() }
-            }
-            else { None }
+            self.events.clear(); // NOTE: events are only kept when read through next_event
+            self.queue.clear();
+            self.step()
         }
     }
 
+/// Iterator over typed events, see Evaluator::events
+pub struct Events<'a, 'e: 'a> {
+    ev: &'a mut Evaluator<'e>,
+}
+
+impl<'a, 'e> Iterator for Events<'a, 'e> {
+    type Item = Event;
+    fn next(&mut self) -> Option<Event> {
+        self.ev.next_event()
+    }
+}
+
 impl<'e> Evaluator<'e> {
     /// Evaluator by default starts on the node named 'root'
     pub fn new (env: &'e mut Env) -> Evaluator<'e> {
         Evaluator {
             env: env,
             node_stack: vec!["root".to_owned()],
+            threads: vec![],
+            thread: MAIN.to_owned(),
+            schedule: Schedule::default(),
+            rr: 0,
+            line_ids: vec![],
+            command: None,
+            events: VecDeque::new(),
+            queue: VecDeque::new(),
+            last: ("root".to_owned(), 0),
+            finished: false,
+            held: Held::default(),
+            waits: HashMap::new(),
+            session: Session::default(),
+        }
+    }
+
+    /// Steps the evaluator, returning the next typed event
+    ///
+    /// This is an alternative to iterating the evaluator directly,
+    /// avoid mixing both while evaluating
+    pub fn next_event (&mut self) -> Option<Event> {
+        self.next_thread_event().map(|(_, e)| e)
+    }
+
+    /// Steps the evaluator, returning the next typed event along with the thread it came from
+    pub fn next_thread_event (&mut self) -> Option<(String,Event)> {
+        loop {
+            self.flush_events();
+            if let Some(e) = self.queue.pop_front() { return Some(e) }
+            if self.finished { return None }
+
+            if self.step().is_none() {
+                // NOTE: picks up again once ticked, or the input is submitted
+                if !self.waits.is_empty() || self.holding(|h| h.prompt.is_some()).is_some() { return None }
+                self.finished = true;
+                let (node, idx) = self.last.clone();
+                self.queue.push_back((MAIN.to_owned(), Event::Finished { node, idx }));
+            }
         }
     }
 
+    /// Queues events from the step run, tagged with its thread
+    fn flush_events (&mut self) {
+        while let Some(e) = self.events.pop_front() {
+            self.queue.push_back((self.thread.clone(), e));
+        }
+    }
+
+    /// Iterates typed events, see next_event
+    pub fn events (&mut self) -> Events<'_, 'e> {
+        Events { ev: self }
+    }
+
+    /// Steps the next thread with anything left to run, see Schedule
+    fn step (&mut self) -> Option<(Vec<Var>, Option<Next>)> {
+        let names: Vec<String> = Some(MAIN.to_owned()).into_iter()
+            .chain(self.threads.iter().map(|t| t.name.clone())).collect();
+        let start = match self.schedule {
+            Schedule::MainFirst => 0,
+            Schedule::RoundRobin => self.rr % names.len(),
+        };
+
+        for i in 0..names.len() {
+            let t = (start + i) % names.len();
+            if let Some(r) = self.step_thread(&names[t]) {
+                self.rr = t + 1;
+                return Some(r)
+            }
+        }
+
+        None
+    }
+
+    /// Steps a single thread, None when it has nothing left to run
+    ///
+    /// Stepping on drops the thread's pending choices and timeout.
+    /// A waiting thread, or one asking for input, steps nothing until ticked or submitted.
+    /// A thread other than main ends once it has nothing left to run
+    pub fn step_thread (&mut self, name: &str) -> Option<(Vec<Var>, Option<Next>)> {
+        if self.waits.contains_key(name) || self.asking(name) { return None }
+
+        let r = self.with_thread(name, |ev| {
+            ev.clear_choices();
+            ev.step_stack()
+        })?;
+        if r.is_none() && name != MAIN {
+            self.threads.retain(|t| t.name != name);
+        }
+
+        r
+    }
+
+    /// Runs with a thread's stack swapped in, None when there's no such thread
+    fn with_thread<T, F> (&mut self, name: &str, f: F) -> Option<T>
+        where F: FnOnce(&mut Evaluator<'e>) -> T {
+        if name == MAIN {
+            let r = f(self);
+            self.flush_events();
+            return Some(r)
+        }
+
+        let i = self.threads.iter().position(|t| t.name == name)?;
+        let stack = mem::take(&mut self.threads[i].stack);
+        let held = mem::take(&mut self.threads[i].held);
+        let main = mem::replace(&mut self.node_stack, stack);
+        let main_held = mem::replace(&mut self.held, held);
+        let places = mem::take(&mut self.threads[i].places);
+        let main_places = self.swap_places(places);
+        self.thread = name.to_owned();
+
+        let r = f(self);
+        self.flush_events();
+
+        let stack = mem::replace(&mut self.node_stack, main);
+        let held = mem::replace(&mut self.held, main_held);
+        let places = self.swap_places(main_places);
+        self.thread = MAIN.to_owned();
+        if let Some(t) = self.threads.iter_mut().find(|t| t.name == name) {
+            // NOTE: looked up again, threads may have changed
+            t.stack = stack;
+            t.held = held;
+            t.places = places;
+        }
+
+        Some(r)
+    }
+
+    /// Swaps in a thread's place in each node, returning the places swapped out
+    ///
+    /// The main thread's places are kept in the source blocks themselves
+    fn swap_places (&mut self, places: HashMap<String,(usize,bool)>) -> HashMap<String,(usize,bool)> {
+        let mut out = HashMap::new();
+        for b in self.env.src.values_mut() {
+            if b.idx > 0 || b.or_valid { out.insert(b.name.clone(), (b.idx, b.or_valid)); }
+
+            let (idx, or_valid) = places.get(&b.name).cloned().unwrap_or((0, false));
+            b.idx = idx;
+            b.or_valid = or_valid;
+        }
+
+        out
+    }
+
+    /// Checks if a thread has asked for input that's yet to be submitted
+    fn asking (&self, name: &str) -> bool {
+        let held = if name == MAIN { Some(&self.held) }
+        else { self.threads.iter().find(|t| t.name == name).map(|t| &t.held) };
+
+        held.and_then(|h| h.prompt.as_ref()).map_or(false, |p| p.asked)
+    }
+
+    /// First thread held on something, main first, then in the order threads were spawned
+    fn holding<F> (&self, f: F) -> Option<(&str, &Held)>
+        where F: Fn(&Held) -> bool {
+        Some((MAIN, &self.held)).into_iter()
+            .chain(self.threads.iter().map(|t| (&t.name[..], &t.held)))
+            .find(|&(_, h)| f(h))
+    }
+
+    /// Runs on the first thread held on something, see holding
+    fn with_holding<T, H, F> (&mut self, held: H, f: F) -> Option<T>
+        where H: Fn(&Held) -> bool, F: FnOnce(&mut Evaluator<'e>) -> T {
+        let thread = self.holding(held)?.0.to_owned();
+        self.with_thread(&thread, f)
+    }
+
+    /// Starts a named thread at a node, unless a thread of that name is running
+    pub fn spawn (&mut self, name: &str, node: &str) -> bool {
+        if name == MAIN || self.threads.iter().any(|t| t.name == name) { return false }
+
+        self.threads.push(Thread::new(name, node));
+        self.finished = false;
+        true
+    }
+
+    /// Names of running threads, main first, then in the order they were spawned
+    pub fn threads (&self) -> Vec<&str> {
+        Some(MAIN).into_iter().chain(self.threads.iter().map(|t| &t.name[..])).collect()
+    }
+
+    /// Advances time by a number of ticks, returning the node a timeout headed to
+    ///
+    /// Waiting threads carry on once their ticks have passed, and a timed select
+    /// or await left pending heads to its timeout's node once its ticks run out.
+    /// When timeouts run out on several threads, the first thread's node is returned
+    pub fn tick (&mut self, ticks: u64) -> Option<String> {
+        for left in self.waits.values_mut() { *left = left.saturating_sub(ticks); }
+        self.waits.retain(|_, left| *left > 0);
+
+        let names: Vec<String> = self.threads().into_iter().map(|t| t.to_owned()).collect();
+        let mut fired = None;
+        for name in names {
+            let target = self.with_thread(&name, |ev| {
+                let timer = ev.held.timer.as_mut()?;
+                timer.left = timer.left.saturating_sub(ticks);
+                if timer.left > 0 { return None }
+
+                let Timer { node, stmt, target, .. } = ev.held.timer.take()?;
+                ev.events.push_back(Event::TimedOut { node, idx: stmt, target: target.clone() });
+                ev.advance(target.clone());
+                Some(target)
+            });
+
+            if fired.is_none() { fired = target.and_then(|t| t); }
+        }
+
+        fired
+    }
+
+    /// Ticks left on the first pending timed select or await, main thread first
+    pub fn timeout (&self) -> Option<u64> {
+        self.holding(|h| h.timer.is_some()).and_then(|(_, h)| h.timer.as_ref()).map(|t| t.left)
+    }
+
+    /// Ticks left before a waiting thread carries on, zero when it isn't waiting
+    pub fn waiting (&self, thread: &str) -> u64 {
+        self.waits.get(thread).cloned().unwrap_or(0)
+    }
+
+    /// Sets the order threads are stepped in
+    pub fn set_schedule (&mut self, schedule: Schedule) {
+        self.schedule = schedule;
+    }
+
+    fn step_stack (&mut self) -> Option<(Vec<Var>, Option<Next>)> {
+        if let Some(r) = self.resume() { return r }
+
+        if let Some(nn) = self.node_stack.pop() {
+            if let Some(r) = self.run(&nn) {
+                // reset node if necessary
+                if let Some(ref next) = r.1 {
+                    match next {
+                        &Next::Restart(ref nn) => {
+                            if let &Some(ref nn) = nn {
+                                if let Some(b) = self.env.src.get_mut(nn) {
+                                    b.idx = 0;
+                                }
+                            }
+                        }
+                        _ => {}, // we handle the rest during Run, for convenience
+                    }
+                }
+                
+                Some(r)
+            }
+            else { self.step_stack() }
+        }
+        else { None }
+    }
+
+    /// Line IDs of the last emitted vars, in the same order
+    ///
+    /// Only strings from source have an ID, other vars are None
+    pub fn line_ids (&self) -> &[Option<String>] {
+        &self.line_ids
+    }
+
+    /// Command from the last step, if it reached one
+    ///
+    /// Commands step with no vars when iterating the evaluator directly
+    pub fn command (&self) -> Option<&Command> {
+        self.command.as_ref()
+    }
+
     /// Consumes Evaluator for saving state
     ///
+    /// Pending choices, input, timeouts, waits and the schedule are kept, so a restore picks up mid-countdown.
     /// You should save the Env state as well, as it's external to the Evaluator
     pub fn save (self) -> EvaluatorState {
         EvaluatorState {
             node_stack: self.node_stack,
+            threads: self.threads,
+            held: self.held,
+            waits: self.waits,
+            schedule: self.schedule,
+            rr: self.rr,
+            session: self.session,
+        }
+    }
+
+    /// Seeds the default random source, for repeatable runs
+    pub fn seed (&mut self, seed: u64) {
+        self.set_rng(Box::new(Random::new(seed)));
+    }
+
+    /// Swaps in another random source
+    pub fn set_rng (&mut self, rng: Box<dyn Rng>) {
+        self.session.rng = RngCell::new(rng);
+    }
+
+    /// Turns, visit counts and chosen entries so far
+    pub fn session (&self) -> &Session {
+        &self.session
+    }
+
+    /// Choices pending from the last select, in source order
+    ///
+    /// When several threads hold choices, the first thread's are listed, main first
+    pub fn choices (&self) -> &[Choice] {
+        self.holding(|h| h.pending.is_some()).map_or(&[], |(_, h)| &h.choices[..])
+    }
+
+    /// Picks a pending choice by index and advances to its target
+    ///
+    /// The thread the choices came from is the one advanced, see choices
+    pub fn choose (&mut self, idx: usize) -> Result<(),&'static str> {
+        self.with_holding(|h| h.pending.is_some(), |ev| ev.choose_held(idx))
+            .unwrap_or(Err("No choices are pending"))
+    }
+
+    fn choose_held (&mut self, idx: usize) -> Result<(),&'static str> {
+        let held = &self.held;
+        let target = match (held.choices.get(idx), held.pending.as_ref()) {
+            (_, None) => return Err("No choices are pending"),
+            (None, _) => return Err("Choice index is out of range"),
+            (Some(c), _) if !c.available => return Err("Choice is unavailable"),
+            (Some(c), Some(p)) => {
+                let chosen = self.session.chosen.entry((p.node.clone(), p.stmt)).or_default();
+                if !chosen.contains(&p.entries[idx]) { chosen.push(p.entries[idx]); }
+
+                c.target.clone()
+            },
+        };
+
+        self.session.turn += 1;
+
+        self.advance(target);
+        Ok(())
+    }
+
+    /// Input pending from the last input statement, main thread first
+    pub fn input (&self) -> Option<&Input> {
+        self.holding(|h| h.prompt.is_some()).and_then(|(_, h)| h.prompt.as_ref()).map(|p| &p.input)
+    }
+
+    /// Submits a var for the pending input
+    ///
+    /// A valid var is written to the input's target and the node resumes,
+    /// otherwise the error is kept and the input is asked for again
+    pub fn submit_input (&mut self, var: Var) -> Result<(),&'static str> {
+        self.with_holding(|h| h.prompt.is_some(), |ev| ev.submit_held(var))
+            .unwrap_or(Err("No input is pending"))
+    }
+
+    fn submit_held (&mut self, var: Var) -> Result<(),&'static str> {
+        let prompt = self.held.prompt.as_mut().ok_or("No input is pending")?;
+        match prompt.input.validate(var) {
+            Ok(v) => {
+                self.env.def.set_path(&prompt.input.target, v);
+                if let Some(b) = self.env.src.get_mut(&prompt.node) {
+                    b.idx = prompt.stmt + 1;
+                }
+
+                self.held.prompt = None;
+                Ok(())
+            },
+            Err(e) => {
+                prompt.error = Some(e.to_owned());
+                prompt.asked = false; // NOTE: the next step asks again, with the error
+                Err(e)
+            },
         }
     }
 
+    fn clear_choices (&mut self) {
+        self.held.choices.clear();
+        self.held.pending = None;
+        self.held.timer = None;
+        self.held.step = None;
+    }
+
+    /// Restores held state put aside by a signal, once its handlers are done
+    ///
+    /// A select or await is yielded again, an input is asked for again on the next step
+    fn resume (&mut self) -> Option<Option<(Vec<Var>, Option<Next>)>> {
+        if self.node_stack.len() > self.held.interrupts.last()?.stack.len() { return None }
+
+        let Interrupt { stack, held } = self.held.interrupts.pop()?;
+        if self.node_stack != stack { return None } // NOTE: handlers headed elsewhere
+
+        let interrupts = mem::take(&mut self.held.interrupts);
+        self.held = held;
+        self.held.interrupts = interrupts;
+        if let Some(ref mut p) = self.held.prompt { p.asked = false; }
+
+        let (event, next) = self.held.step.clone()?;
+        self.events.push_back(event);
+        Some(Some((vec![], Some(next))))
+    }
+
     /// Manually advances Evaluator to next node
     pub fn advance (&mut self, node: String) {
         // reset last node on advance
         if let Some(node_) = self.node_stack.pop() {
             if let Some(b) = self.env.src.get_mut(&node_) {
+                self.events.push_back(Event::NodeExited { node: node_.clone(), idx: b.idx });
                 b.idx = 0;
             }
         }
@@ -120,19 +633,162 @@ impl<'e> Evaluator<'e> {
         // successful advance clears out stack
         self.node_stack.clear();
         self.node_stack.push(node);
+        self.waits.remove(&self.thread);
+        self.finished = false;
+        self.clear_choices();
+        self.held.prompt = None;
+        self.held.interrupts.clear();
     }
 
-    pub fn resolve (s: &str, logic: &HashMap<String,LogicFn>, def: &HashMap<String,DefBlock>) -> Option<Var> {
+    /// Resolves a symbol as logic, a def var, or a session counter
+    pub fn resolve (s: &str,
+                    logic: &HashMap<String,LogicFn>,
+                    def: &HashMap<String,DefBlock>,
+                    session: &Session) -> Option<Var> {
         if let Some(ref lfn) = logic.get(s) {
-            if let Some(val_) = lfn.run(&def, &logic) {
+            if let Some(val_) = lfn.run(&def, &logic, session) {
                 return Some(val_.into())
             }
+
+            return None
         }
         else if let Some((v,res)) = def.get_last(s) {
             if res { return Some(v) }
         }
 
-        None
+        session.get(s)
+    }
+
+    /// Checks a node's guard without entering it, eg: before advancing
+    ///
+    /// Err holds the failed guard, along with any fallback node
+    pub fn check_guard (&mut self, node: &str) -> Result<(),Guard> {
+        if let Some(b) = self.env.src.get_mut(node) {
+            if !Evaluator::guard(b, &self.env.def, &self.session) {
+                return Err(b.guard.clone().unwrap())
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Lists storylets eligible to start, highest priority first, optionally only those with a tag
+    ///
+    /// A storylet is eligible when its guard passes, and it's repeatable or not yet visited.
+    /// Equal priorities are listed by node name
+    pub fn storylets (&mut self, tag: Option<&str>) -> Vec<String> {
+        let mut eligible: Vec<(Float, String)> = vec![];
+        for b in self.env.src.values_mut() {
+            let priority = match b.storylet {
+                Some(ref s) if s.repeatable || self.session.visits(&b.name) == 0 => {
+                    match self.session.get_number(&s.priority, &self.env.def) {
+                        Ok(Var::Int(n)) => n as Float,
+                        Ok(Var::Num(n)) => n,
+                        _ => 0.0,
+                    }
+                },
+                _ => continue,
+            };
+
+            if tag.map_or(false, |t| !b.has_tag(t)) { continue }
+            if !Evaluator::guard(b, &self.env.def, &self.session) { continue }
+
+            eligible.push((priority, b.name.clone()));
+        }
+
+        eligible.sort_by(|a, b| {
+            b.0.partial_cmp(&a.0).unwrap_or(Ordering::Equal).then_with(|| a.1.cmp(&b.1))
+        });
+        eligible.into_iter().map(|(_, name)| name).collect()
+    }
+
+    /// Starts an eligible storylet, advancing to it
+    pub fn start_storylet (&mut self, node: &str) -> Result<(),&'static str> {
+        if !self.storylets(None).iter().any(|s| s == node) {
+            return Err("Storylet is not eligible")
+        }
+
+        self.advance(node.to_owned());
+        Ok(())
+    }
+
+    /// Sends a signal, interrupting evaluation with any nodes handling it
+    ///
+    /// Handlers are called as with next:call, highest priority first, and
+    /// evaluation picks up where it was afterwards. Handlers already on the
+    /// stack are skipped. A pending select, await or input is put aside while
+    /// handlers run, and yielded again once they're done, with any timeout paused.
+    /// Returns the handlers in the order they'll run
+    pub fn signal (&mut self, name: &str, args: &[Var]) -> Vec<String> {
+        let same = |a: &Var, b: &Var| match a.cmp_num(b) {
+            Some(o) => o == Ordering::Equal,
+            None => a.to_string() == b.to_string(),
+        };
+
+        let mut handlers: Vec<(Float, String)> = vec![];
+        for b in self.env.src.values() {
+            if let Some(ref on) = b.on {
+                if on.name != name || on.args.len() > args.len() { continue }
+                if !on.args.iter().zip(args).all(|(a, b)| same(a, b)) { continue }
+                if self.node_stack.contains(&b.name) { continue }
+
+                let priority = match self.session.get_number(&on.priority, &self.env.def) {
+                    Ok(Var::Int(n)) => n as Float,
+                    Ok(Var::Num(n)) => n,
+                    _ => 0.0,
+                };
+                handlers.push((priority, b.name.clone()));
+            }
+        }
+
+        handlers.sort_by(|a, b| {
+            b.0.partial_cmp(&a.0).unwrap_or(Ordering::Equal).then_with(|| a.1.cmp(&b.1))
+        });
+
+        if !handlers.is_empty() && (self.held.step.is_some() || self.held.prompt.is_some()) {
+            let mut interrupts = mem::take(&mut self.held.interrupts);
+            interrupts.push(Interrupt { stack: self.node_stack.clone(), held: mem::take(&mut self.held) });
+            self.held.interrupts = interrupts;
+        }
+
+        // NOTE: the stack runs from the top, so the first handler goes on last
+        for (_, nn) in handlers.iter().rev() {
+            self.node_stack.push(nn.clone());
+        }
+        if !handlers.is_empty() { self.finished = false; }
+
+        handlers.into_iter().map(|(_, nn)| nn).collect()
+    }
+
+    /// Queries bark rules with facts, against the current def and session
+    ///
+    /// Returns the best matching rule's node, for the host to advance to or run elsewhere
+    pub fn bark (&mut self, barks: &mut Barks, facts: &HashMap<String,Var>, now: u64) -> Option<String> {
+        barks.query(facts, &mut self.env.def, &self.session, now)
+    }
+
+    /// Checks a node's guard, compiling the guard's logic if it's in the node
+    ///
+    /// Guards resolve as if statements do, a node without one always passes
+    fn guard (b: &mut SrcBlock, def: &Def, session: &Session) -> bool {
+        let requires = match b.guard {
+            Some(ref g) => g.requires.clone(),
+            None => return true,
+        };
+
+        if !b.logic.contains_key(&requires) {
+            for src in b.src.iter() {
+                if let Src::Logic(ref name, ref logic) = *src {
+                    if name == &requires { b.logic.insert(name.clone(), logic.eval()); }
+                }
+            }
+        }
+
+        match Evaluator::resolve(&requires, &b.logic, def, session) {
+            Some(Var::Bool(v)) => v,
+            Some(Var::Nil) | None => false,
+            Some(val) => requires != val.to_string(),
+        }
     }
 
     /// Manually run the Evaluator, starting at node specified
@@ -140,12 +796,36 @@ impl<'e> Evaluator<'e> {
                 -> Option<(Vec<Var>, Option<Next>)>
     {
         if let Some(b) = self.env.src.get_mut(node_name) {
+            let reprompt = self.held.prompt.as_ref().map_or(false, |p| p.node == node_name && p.stmt == b.idx);
+            
+            // entering a guarded node, refused entry heads to any fallback instead
+            if b.idx == 0 && !reprompt && !b.src.is_empty() &&
+                !Evaluator::guard(b, &self.env.def, &self.session) {
+                let guard = b.guard.clone().unwrap();
+                self.events.push_back(Event::Refused { node: node_name.to_owned(),
+                                                       idx: 0,
+                                                       guard: guard.requires,
+                                                       fallback: guard.fallback.clone() });
+                if let Some(nn) = guard.fallback { self.node_stack.push(nn); }
+                
+                return None
+            }
+            
             b.visited = true;
             
             if let Some(src) = b.src.get(b.idx) {
                 self.node_stack.push(node_name.to_owned()); //more to iterate through?
+                let stmt = b.idx;
                 b.idx += 1;
                 
+                self.last = (node_name.to_owned(), stmt);
+                if stmt == 0 && !reprompt {
+                    self.session.visit(node_name);
+                    self.events.push_back(Event::NodeEntered { node: node_name.to_owned(),
+                                                               idx: stmt,
+                                                               tags: b.tags.clone() });
+                }
+                
                 match src {
                     &Src::Or(_,_) => {
                         if !b.or_valid {
@@ -158,62 +838,55 @@ impl<'e> Evaluator<'e> {
                     _ => { b.or_valid = false; },
                 }
 
-                let (mut vars, next) = src.eval(&mut b.logic,
-                                                &mut self.env.def,
-                                                &mut self.env.fun);
-                let has_return = (vars.len() > 0) || next.is_some();
+                // variations pick a single alternative, see session counters
+                let mut picked = None;
+                let (mut vars, mut next) = match *src {
+                    Src::Vary(kind, ref alts) => {
+                        picked = self.session.vary(node_name, stmt, kind, alts.len());
+                        (picked.map(|i| alts[i].clone()).into_iter().collect(), None)
+                    },
+                    _ => src.eval(&mut b.logic,
+                                  &mut self.env.def,
+                                  &mut self.env.fun,
+                                  &self.session),
+                };
+                let locale = self.env.locales.get(&self.env.locale);
+                let mut has_return = (vars.len() > 0) || next.is_some();
                
                 // reset when if is successful
                 if has_return { b.or_valid = false; }
                 
+                // emitted strings line up with the statement's lines, in order
+                let lines = Line::statement(b, stmt);
+                let mut lines = lines.iter();
+                if let (Some(i), Src::Vary(_, alts)) = (picked, src) {
+                    let skip = alts[..i].iter().filter(|v| matches!(**v, Var::String(_))).count();
+                    for _ in 0..skip { let _ = lines.next(); }
+                }
+                self.line_ids.clear();
 
                 for var in vars.iter_mut() {
                     let mut val = None;
+                    let mut id = None;
                     
                     match var {
                         &mut Var::Sym(ref mut s) => { // resolve symbol refs
-                            val = Evaluator::resolve(s, &b.logic, &self.env.def);
+                            val = Evaluator::resolve(s, &b.logic, &self.env.def, &self.session);
                             // NOTE: otherwise we silently fail
                         },
                         &mut Var::String(ref mut s) => { //format string
-                            let mut fs = String::new();
-                            let mut sym = String::new();
-                            let mut in_sym = false;
-                            
-                            for c in s.chars() {
-                                if (c == ' ' || c == '`') && !sym.is_empty() {
-                                    if let Some(v) = Evaluator::resolve(&sym, &b.logic, &self.env.def) {
-                                        fs.push_str(&v.to_string());
-                                    }
-                                    else {
-                                        fs.push_str(&sym); //push as non-ref sym again
-                                        // NOTE: we should consider failing silently (dont push)
-                                    }
-
-                                    if c == '`' { in_sym = true; }
-                                    else {
-                                        in_sym = false;
-                                        sym.clear();
-                                        fs.push(' ');
-                                    }
-                                }
-                                else if c == '`' { in_sym = true; }
-                                else {
-                                    if in_sym { sym.push(c); }
-                                    else { fs.push(c); }
+                            if let Some(line) = lines.next() {
+                                if let Some(t) = locale.and_then(|l| l.get(&line.id)) {
+                                    *s = t.to_owned(); // translated text is formatted as usual
                                 }
+                                
+                                id = Some(line.id.clone());
                             }
-
-                            if !sym.is_empty() {
-                                if let Some(v) = Evaluator::resolve(&sym, &b.logic, &self.env.def) {
-                                    fs.push_str(&v.to_string());
-                                }
-                                else {
-                                    fs.push_str(&sym);
-                                }
-                            }
-
-                            *s = fs;
+                            
+                            *s = Interp::new(&b.logic, &self.env.def)
+                                .with_session(&self.session)
+                                .with_plural(self.env.plurals.get(&self.env.locale))
+                                .format(s);
                         },
                         _ => {}
                     }
@@ -221,6 +894,156 @@ impl<'e> Evaluator<'e> {
                     if let Some(val) = val {
                         *var = val;
                     }
+                    
+                    self.line_ids.push(id);
+                }
+
+                // commands resolve their args as emitted vars would, without line IDs
+                self.command = None;
+                if let Src::Command(ref name, ref args) = *src {
+                    let (def, session) = (&self.env.def, &self.session);
+                    let plural = self.env.plurals.get(&self.env.locale);
+                    let args: Vec<Var> = args.iter().map(|a| {
+                        match *a {
+                            Var::Sym(ref s) => {
+                                Evaluator::resolve(s, &b.logic, def, session)
+                                    .unwrap_or_else(|| a.clone())
+                            },
+                            Var::String(ref s) => {
+                                Interp::new(&b.logic, def)
+                                    .with_session(session)
+                                    .with_plural(plural)
+                                    .format(s).into()
+                            },
+                            _ => a.clone(),
+                        }
+                    }).collect();
+
+                    self.events.push_back(Event::Command {
+                        node: node_name.to_owned(),
+                        idx: stmt,
+                        name: name.clone(),
+                        args: args.clone(),
+                    });
+                    self.command = Some(Command { name: name.clone(), args });
+                    has_return = true;
+                }
+                
+                if let (Some(&mut Next::Select(ref mut select)), Some(locale)) = (next.as_mut(), locale) {
+                    select.map = locale.translate_map(&b.name, &select.map);
+                }
+                
+                // random jumps pick their node now
+                if let Some(Next::Random(ref map)) = next {
+                    let picked = self.session.pick(map, &self.env.def);
+                    next = picked.map(Next::Now);
+                    has_return = !vars.is_empty() || next.is_some();
+                }
+                
+                // chosen once-only entries are dropped, once they've left nothing to pick the select falls through
+                let mut exhausted = None;
+                if let Some(Next::Select(ref select)) = next {
+                    let chosen = self.session.chosen.get(&(node_name.to_owned(), stmt));
+                    let listed = Choice::list(&select.map, &b.logic, &self.env.def, &self.session);
+                    let total = listed.len();
+                    let (entries, choices): (Vec<usize>, Vec<Choice>) = listed
+                        .into_iter().enumerate()
+                        .filter(|(i, c)| !(c.once && chosen.map_or(false, |ch| ch.contains(i))))
+                        .unzip();
+
+                    // NOTE: a select only guarded out is still listed, so it can be shown greyed out
+                    if choices.len() < total && !choices.iter().any(|c| c.available) {
+                        exhausted = Some(select.fallback.clone().map(Next::Now));
+                    }
+                    else {
+                        self.held.choices = choices;
+                        self.held.pending = Some(Pending { node: node_name.to_owned(), stmt, entries });
+                    }
+                }
+                if let Some(fallback) = exhausted {
+                    next = fallback;
+                    has_return = !vars.is_empty() || next.is_some();
+                }
+
+                // timed selects and awaits run out as the host ticks
+                let timeout = match next {
+                    Some(Next::Select(Select { timeout: Some(ref t), .. })) => Some(t),
+                    Some(Next::AwaitFor(_, ref t)) => Some(t),
+                    _ => None,
+                };
+                self.held.timer = timeout.map(|t| Timer { node: node_name.to_owned(),
+                                                          stmt,
+                                                          left: t.ticks,
+                                                          target: t.target.clone() });
+
+                // waits pause the thread until enough ticks have passed
+                if let Src::Wait(ticks) = *src {
+                    if ticks > 0 { self.waits.insert(self.thread.clone(), ticks); }
+                    self.events.push_back(Event::Wait { node: node_name.to_owned(), idx: stmt, ticks });
+                    has_return = true;
+                }
+                
+                // speakers step as the first var, as text
+                if let Src::Say(ref speaker, _) = *src {
+                    let speaker = match vars[0] {
+                        Var::Sym(_) => speaker.clone(), // NOTE: unresolved, the symbol is its id
+                        ref v => v.to_string(),
+                    };
+                    let text = vars[1].to_string();
+                    let tags = b.line_tags(stmt).to_vec();
+                    vars[0] = Var::String(speaker.clone());
+                    self.events.push_back(Event::Say {
+                        node: node_name.to_owned(),
+                        idx: stmt,
+                        speaker,
+                        text,
+                        id: self.line_ids[1].clone(),
+                        tags,
+                    });
+                }
+                else if !vars.is_empty() {
+                    self.events.push_back(Event::Line {
+                        node: node_name.to_owned(),
+                        idx: stmt,
+                        vars: vars.clone(),
+                        ids: self.line_ids.clone(),
+                        tags: b.line_tags(stmt).to_vec(),
+                    });
+                }
+                match next {
+                    Some(Next::Select(_)) => {
+                        let event = Event::Choices {
+                            node: node_name.to_owned(),
+                            idx: stmt,
+                            choices: self.held.choices.clone(),
+                        };
+                        self.held.step = next.clone().map(|n| (event.clone(), n));
+                        self.events.push_back(event);
+                    },
+                    Some(Next::Await(ref nn)) | Some(Next::AwaitFor(ref nn, _)) => {
+                        let event = Event::AwaitAdvance {
+                            node: node_name.to_owned(),
+                            idx: stmt,
+                            target: nn.clone(),
+                        };
+                        self.held.step = next.clone().map(|n| (event.clone(), n));
+                        self.events.push_back(event);
+                    },
+                    Some(Next::Input(ref input)) => {
+                        let error = self.held.prompt.take().filter(|_| reprompt).and_then(|p| p.error);
+                        self.events.push_back(Event::Input {
+                            node: node_name.to_owned(),
+                            idx: stmt,
+                            input: input.clone(),
+                            error: error.clone(),
+                        });
+                        self.held.prompt = Some(Prompt { node: node_name.to_owned(),
+                                                         stmt,
+                                                         input: input.clone(),
+                                                         error,
+                                                         asked: true });
+                    },
+                    _ => {},
                 }
                 
                 if let Some(ref next) = next {
@@ -248,9 +1071,24 @@ impl<'e> Evaluator<'e> {
                             self.node_stack.push(b.name.to_owned());
                         },
                         &Next::Exit => { self.node_stack.clear(); },
-                        &Next::Await(_) | &Next::Select(_) => {
+                        &Next::Await(_) | &Next::AwaitFor(_,_) | &Next::Select(_) => {
                             b.idx = idx;
                         },
+                        &Next::Random(_) => {}, // NOTE: picked above, as Now
+                        &Next::Input(_) => {
+                            b.idx = stmt; // asks again until submitted
+                        },
+                        Next::Spawn(nn, name) => { // runs alongside, on a thread of its own
+                            b.idx = idx;
+                            if name != MAIN && !self.threads.iter().any(|t| &t.name == name) {
+                                self.threads.push(Thread::new(name, nn));
+                            }
+                        },
+                    }
+                    
+                    // leaving the node resets its place
+                    if b.idx == 0 && !matches!(*next, Next::Input(_)) {
+                        self.events.push_back(Event::NodeExited { node: node_name.to_owned(), idx: stmt });
                     }
                 }
 
@@ -261,7 +1099,12 @@ impl<'e> Evaluator<'e> {
                     return None
                 }
             }
-            else { b.idx = 0; } //reset
+            else { //reset
+                if b.idx > 0 {
+                    self.events.push_back(Event::NodeExited { node: node_name.to_owned(), idx: b.idx });
+                }
+                b.idx = 0;
+            }
         }
 
         None
@@ -271,6 +1114,12 @@ impl<'e> Evaluator<'e> {
 #[derive(Clone,Debug)]
 pub struct EvaluatorState {
     node_stack: Vec<String>,
+    threads: Vec<Thread>,
+    held: Held,
+    waits: HashMap<String,u64>,
+    schedule: Schedule,
+    rr: usize,
+    session: Session,
 }
 
 impl EvaluatorState {
@@ -278,6 +1127,19 @@ impl EvaluatorState {
         Evaluator {
             env: env,
             node_stack: self.node_stack,
+            threads: self.threads,
+            thread: MAIN.to_owned(),
+            schedule: self.schedule,
+            rr: self.rr,
+            line_ids: vec![],
+            command: None,
+            events: VecDeque::new(),
+            queue: VecDeque::new(),
+            last: ("root".to_owned(), 0),
+            finished: false,
+            held: self.held,
+            waits: self.waits,
+            session: self.session,
         }
     }
 
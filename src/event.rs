@@ -0,0 +1,86 @@
+use var::Var;
+use source::Choice;
+use input::Input;
+
+/// Typed evaluation events, see Evaluator::next_event
+///
+/// Each event carries the node name and statement index it came from
+#[derive(Debug,PartialEq,Clone)]
+pub enum Event {
+    /// Node started from its first statement, along with the node's tags
+    NodeEntered { node: String, idx: usize, tags: Vec<String> },
+
+    /// Node's guard failed on entry, heading to the fallback if it has one
+    Refused { node: String, idx: usize, guard: String, fallback: Option<String> },
+
+    /// Node finished, or left through next
+    NodeExited { node: String, idx: usize },
+
+    /// Emitted vars, along with the line ID of each var and the statement's tags
+    Line { node: String, idx: usize, vars: Vec<Var>, ids: Vec<Option<String>>, tags: Vec<String> },
+
+    /// Line said by a speaker, along with its line ID and tags
+    ///
+    /// Speaker is the resolved speaker var, or its symbol when unresolved
+    Say { node: String, idx: usize, speaker: String, text: String, id: Option<String>, tags: Vec<String> },
+
+    /// Choices to present in order, continues current node if not chosen
+    Choices { node: String, idx: usize, choices: Vec<Choice> },
+
+    /// Awaits manual advancement to target node
+    AwaitAdvance { node: String, idx: usize, target: String },
+
+    /// Awaits input for a var, see Evaluator::submit_input
+    ///
+    /// Error is why the last submission was refused
+    Input { node: String, idx: usize, input: Input, error: Option<String> },
+
+    /// Command for the host to run, with resolved args
+    Command { node: String, idx: usize, name: String, args: Vec<Var> },
+
+    /// Evaluation pauses on this thread until the ticks have passed, see Evaluator::tick
+    Wait { node: String, idx: usize, ticks: u64 },
+
+    /// Timed select or await ran out, heading to the target node
+    TimedOut { node: String, idx: usize, target: String },
+
+    /// Evaluation ended, node and index are of the last statement run
+    Finished { node: String, idx: usize },
+}
+
+impl Event {
+    pub fn node (&self) -> &str {
+        match *self {
+            Event::NodeEntered { ref node, .. } |
+            Event::NodeExited { ref node, .. } |
+            Event::Refused { ref node, .. } |
+            Event::Line { ref node, .. } |
+            Event::Say { ref node, .. } |
+            Event::Choices { ref node, .. } |
+            Event::AwaitAdvance { ref node, .. } |
+            Event::Input { ref node, .. } |
+            Event::Command { ref node, .. } |
+            Event::Wait { ref node, .. } |
+            Event::TimedOut { ref node, .. } |
+            Event::Finished { ref node, .. } => node,
+        }
+    }
+
+    /// Statement index within the node
+    pub fn idx (&self) -> usize {
+        match *self {
+            Event::NodeEntered { idx, .. } |
+            Event::NodeExited { idx, .. } |
+            Event::Refused { idx, .. } |
+            Event::Line { idx, .. } |
+            Event::Say { idx, .. } |
+            Event::Choices { idx, .. } |
+            Event::AwaitAdvance { idx, .. } |
+            Event::Input { idx, .. } |
+            Event::Command { idx, .. } |
+            Event::Wait { idx, .. } |
+            Event::TimedOut { idx, .. } |
+            Event::Finished { idx, .. } => idx,
+        }
+    }
+}
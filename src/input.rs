@@ -0,0 +1,241 @@
+use std::cmp::Ordering;
+
+use var::Var;
+use parse::IR;
+
+/// Input request, the var to write and any checks the input must pass
+///
+/// eg: next:input @player.name max 12 pattern "[A-Za-z ]+"
+#[derive(Debug,PartialEq,Clone)]
+pub struct Input {
+    /// Path of the var to write, without the '@'
+    pub target: String,
+    pub checks: Vec<Check>,
+}
+
+/// Input validation
+#[derive(Debug,PartialEq,Clone)]
+pub enum Check {
+    /// Must be a number, eg: number
+    Number,
+    /// Must be a number within the inclusive range, eg: range 1 120
+    Range(Var,Var),
+    /// Text no longer than this many characters, eg: max 12
+    Max(usize),
+    /// Text must fully match the pattern, eg: pattern "[a-z]+"
+    Pattern(Pattern),
+}
+
+impl Input {
+    /// Parses the target and checks following next:input
+    pub fn parse (mut exp: Vec<IR>) -> Result<Input,&'static str> {
+        if exp.is_empty() { return Err("Input is missing its @target") }
+
+        let target: String = exp.remove(0).into();
+        let target = match target.strip_prefix('@') {
+            Some(t) if !t.is_empty() => t.to_owned(),
+            _ => return Err("Input is missing its @target"),
+        };
+        // NOTE: vars are only written within a block, eg: @player.name
+        if target.split('.').count() < 2 || target.split('.').any(|k| k.is_empty()) {
+            return Err("Input target must be a block.field path")
+        }
+
+        let mut checks = vec![];
+        let mut exp = exp.into_iter();
+        while let Some(ir) = exp.next() {
+            let check: String = ir.into();
+            match &check[..] {
+                "number" => checks.push(Check::Number),
+                "range" => {
+                    let (min, max) = match (exp.next(), exp.next()) {
+                        (Some(min), Some(max)) => (Var::parse(min)?, Var::parse(max)?),
+                        _ => return Err("Input range is missing its bounds"),
+                    };
+
+                    if min.cmp_num(&max).is_none() { return Err("Input range bounds must be numbers") }
+
+                    checks.push(Check::Range(min, max));
+                },
+                "max" => {
+                    let max: String = exp.next().ok_or("Input max is missing its length")?.into();
+                    checks.push(Check::Max(max.parse().map_err(|_| "Input max must be a length")?));
+                },
+                "pattern" => {
+                    let pattern: String = exp.next().ok_or("Input pattern is missing")?.into();
+                    checks.push(Check::Pattern(Pattern::parse(&pattern)?));
+                },
+                _ => return Err("Unknown input check"),
+            }
+        }
+
+        Ok(Input { target, checks })
+    }
+
+    /// Validates input, returning the var to write
+    ///
+    /// Text is read as a number when a number is expected
+    pub fn validate (&self, var: Var) -> Result<Var,&'static str> {
+        let numeric = self.checks.iter().any(|c| matches!(*c, Check::Number | Check::Range(_,_)));
+        let var = match var {
+            Var::String(ref s) if numeric => {
+                match Var::parse(IR::Sym(s.trim().to_owned())) {
+                    Ok(n @ Var::Int(_)) | Ok(n @ Var::Num(_)) => n,
+                    _ => return Err("Input must be a number"),
+                }
+            },
+            var => var,
+        };
+
+        for check in self.checks.iter() {
+            match *check {
+                Check::Number => {
+                    if var.cmp_num(&Var::Int(0)).is_none() { return Err("Input must be a number") }
+                },
+                Check::Range(ref min, ref max) => {
+                    let below = var.cmp_num(min).map_or(true, |o| o == Ordering::Less);
+                    let above = var.cmp_num(max).map_or(true, |o| o == Ordering::Greater);
+                    if below || above { return Err("Input is out of range") }
+                },
+                Check::Max(max) => {
+                    if var.to_string().chars().count() > max { return Err("Input is too long") }
+                },
+                Check::Pattern(ref p) => {
+                    if !p.matches(&var.to_string()) { return Err("Input does not match the pattern") }
+                },
+            }
+        }
+
+        Ok(var)
+    }
+}
+
+/// A small regex-like pattern, which must match the whole input
+///
+/// Supports literals, '.', classes such as [a-z_] or [^0-9],
+/// the escapes \d \w \s, and the quantifiers ? * +
+#[derive(Debug,PartialEq,Clone)]
+pub struct Pattern(Vec<Piece>);
+
+#[derive(Debug,PartialEq,Clone)]
+struct Piece {
+    atom: Atom,
+    min: usize,
+    max: Option<usize>,
+}
+
+#[derive(Debug,PartialEq,Clone)]
+enum Atom {
+    Any,
+    Char(char),
+    /// Inclusive char ranges, and if the class is negated
+    Class(Vec<(char,char)>, bool),
+}
+
+impl Atom {
+    fn matches (&self, c: char) -> bool {
+        match *self {
+            Atom::Any => true,
+            Atom::Char(a) => a == c,
+            Atom::Class(ref ranges, negated) => {
+                ranges.iter().any(|&(lo, hi)| lo <= c && c <= hi) != negated
+            },
+        }
+    }
+
+    fn escape (c: char) -> Atom {
+        match c {
+            'd' => Atom::Class(vec![('0','9')], false),
+            'w' => Atom::Class(vec![('a','z'), ('A','Z'), ('0','9'), ('_','_')], false),
+            's' => Atom::Class(vec![(' ',' '), ('\t','\t'), ('\n','\n'), ('\r','\r')], false),
+            _ => Atom::Char(c),
+        }
+    }
+}
+
+impl Pattern {
+    pub fn parse (src: &str) -> Result<Pattern,&'static str> {
+        let mut pieces: Vec<Piece> = vec![];
+        let mut chars = src.chars();
+
+        while let Some(c) = chars.next() {
+            let atom = match c {
+                '.' => Atom::Any,
+                '\\' => Atom::escape(chars.next().ok_or("Input pattern ends in an escape")?),
+                '[' => {
+                    let mut ranges = vec![];
+                    let mut negated = false;
+                    let mut first = true;
+                    loop {
+                        let c = chars.next().ok_or("Input pattern has an unclosed class")?;
+                        match c {
+                            ']' if !first => break,
+                            '^' if first => { negated = true; continue },
+                            '\\' => {
+                                match Atom::escape(chars.next().ok_or("Input pattern ends in an escape")?) {
+                                    Atom::Class(r, _) => ranges.extend(r),
+                                    Atom::Char(c) => ranges.push((c, c)),
+                                    Atom::Any => {},
+                                }
+                            },
+                            '-' if !ranges.is_empty() && !chars.as_str().starts_with(']') => {
+                                let hi = chars.next().ok_or("Input pattern has an unclosed class")?;
+                                let (lo, _) = ranges.pop().unwrap();
+                                if hi < lo { return Err("Input pattern has an invalid range") }
+                                ranges.push((lo, hi));
+                            },
+                            c => ranges.push((c, c)),
+                        }
+                        first = false;
+                    }
+
+                    Atom::Class(ranges, negated)
+                },
+                '?' | '*' | '+' => {
+                    let piece = pieces.last_mut().ok_or("Input pattern quantifier has nothing to repeat")?;
+                    if piece.min != 1 || piece.max != Some(1) {
+                        return Err("Input pattern has a repeated quantifier")
+                    }
+
+                    match c {
+                        '?' => { piece.min = 0; },
+                        '*' => { piece.min = 0; piece.max = None; },
+                        _ => { piece.max = None; },
+                    }
+                    continue
+                },
+                c => Atom::Char(c),
+            };
+
+            pieces.push(Piece { atom, min: 1, max: Some(1) });
+        }
+
+        Ok(Pattern(pieces))
+    }
+
+    pub fn matches (&self, text: &str) -> bool {
+        let chars: Vec<char> = text.chars().collect();
+        Pattern::match_from(&self.0, &chars)
+    }
+
+    /// Backtracks through each piece, preferring longer repeats
+    fn match_from (pieces: &[Piece], chars: &[char]) -> bool {
+        let piece = match pieces.first() {
+            Some(p) => p,
+            None => return chars.is_empty(),
+        };
+
+        let mut n = 0;
+        while n < chars.len() && piece.max.map_or(true, |max| n < max) && piece.atom.matches(chars[n]) {
+            n += 1;
+        }
+
+        while n >= piece.min {
+            if Pattern::match_from(&pieces[1..], &chars[n..]) { return true }
+            if n == 0 { break }
+            n -= 1;
+        }
+
+        false
+    }
+}
@@ -0,0 +1,705 @@
+use std::cmp::Ordering;
+use std::fmt;
+
+use var::{Var,Mut,Float};
+use logic::Logics;
+use def::Def;
+use eval::Evaluator;
+use session::Session;
+
+/// Plural categories, named as in the CLDR plural rules
+#[derive(Debug,Clone,Copy,PartialEq)]
+pub enum Plural {
+    Zero,
+    One,
+    Two,
+    Few,
+    Many,
+    Other,
+}
+
+impl Plural {
+    pub fn parse (s: &str) -> Option<Plural> {
+        match s {
+            "zero" => Some(Plural::Zero),
+            "one" => Some(Plural::One),
+            "two" => Some(Plural::Two),
+            "few" => Some(Plural::Few),
+            "many" => Some(Plural::Many),
+            "other" => Some(Plural::Other),
+            _ => None,
+        }
+    }
+}
+
+/// Per-locale plural rules, provided by the host
+///
+/// Categories lists the order of positional forms, eg: {coins|coin|coins}
+/// where english uses [One, Other]
+pub struct PluralRules {
+    pub categories: Vec<Plural>,
+    rule: Box<dyn Fn(Float) -> Plural + Send>,
+}
+
+impl PluralRules {
+    pub fn new<F> (categories: Vec<Plural>, rule: F) -> PluralRules
+        where F: 'static + Send + Fn(Float) -> Plural {
+        PluralRules { categories, rule: Box::new(rule) }
+    }
+
+    /// Default rules, 1 is singular and everything else is plural
+    pub fn english () -> PluralRules {
+        PluralRules::new(vec![Plural::One, Plural::Other], |n| {
+            if n == 1. { Plural::One }
+            else { Plural::Other }
+        })
+    }
+
+    pub fn select (&self, n: Float) -> Plural {
+        (self.rule)(n)
+    }
+}
+
+// NOTE: we don't actually impl this, but satisfy checker
+impl fmt::Debug for PluralRules {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f,"{:?}", self.categories)
+    }
+}
+
+/// String formatter for emitted strings
+///
+/// Replaces any `{expression:spec}` regions with their evaluated value,
+/// and any older backtick-prefixed symbols with their referenced value
+pub struct Interp<'a> {
+    logic: &'a Logics,
+    def: &'a Def,
+    plural: Option<&'a PluralRules>,
+    session: Option<&'a Session>,
+}
+
+impl<'a> Interp<'a> {
+    pub fn new (logic: &'a Logics, def: &'a Def) -> Interp<'a> {
+        Interp { logic, def, plural: None, session: None }
+    }
+
+    /// Resolves session counters, eg: {tavern.visits}
+    pub fn with_session (mut self, session: &'a Session) -> Interp<'a> {
+        self.session = Some(session);
+        self
+    }
+
+    /// Uses these plural rules for plural forms, english rules are used otherwise
+    pub fn with_plural (mut self, plural: Option<&'a PluralRules>) -> Interp<'a> {
+        self.plural = plural;
+        self
+    }
+
+    /// Formats a string, see docs/syntax.md for the supported expressions
+    ///
+    /// Double braces escape a literal brace, eg: '{{' becomes '{'.
+    /// Regions which fail to parse, or are only a name that's unresolved, are left in place as written.
+    /// Unresolved names within an expression are nil, eg: '{has_key ? 'open' : 'locked'}'
+    pub fn format (&self, s: &str) -> String {
+        let chars: Vec<char> = s.chars().collect();
+        let mut fs = String::new();
+        let mut text = String::new(); // literal text between regions
+        let mut i = 0;
+
+        while i < chars.len() {
+            let c = chars[i];
+            if (c == '{' || c == '}') && chars.get(i+1) == Some(&c) {
+                text.push(c);
+                i += 2;
+                continue
+            }
+
+            if c == '{' {
+                if let Some(end) = Interp::region_end(&chars, i) {
+                    fs.push_str(&self.format_legacy(&text));
+                    text.clear();
+
+                    let region: String = chars[i+1..end].iter().collect();
+                    match self.eval_region(&region) {
+                        Some(r) => fs.push_str(&r),
+                        None => {
+                            fs.push('{');
+                            fs.push_str(&region);
+                            fs.push('}');
+                        }
+                    }
+
+                    i = end + 1;
+                    continue
+                }
+            }
+
+            text.push(c);
+            i += 1;
+        }
+
+        fs.push_str(&self.format_legacy(&text));
+        fs
+    }
+
+    /// Evaluates an expression on its own, eg: 'player.gold > 10'
+    pub fn eval (&self, exp: &str) -> Option<Var> {
+        let mut p = Exp::new(exp, self);
+        let v = p.ternary()?;
+        p.skip_ws();
+        if p.done() { Some(v) }
+        else { None }
+    }
+
+    /// Finds the closing brace for a region, skipping nested braces and quotes
+    fn region_end (chars: &[char], start: usize) -> Option<usize> {
+        let mut depth = 0;
+        let mut quote = None;
+
+        for (i,&c) in chars.iter().enumerate().skip(start) {
+            if let Some(q) = quote {
+                if c == q { quote = None; }
+                continue
+            }
+
+            match c {
+                '\'' | '"' => { quote = Some(c); },
+                '{' => { depth += 1; },
+                '}' => {
+                    depth -= 1;
+                    if depth == 0 { return Some(i) }
+                },
+                _ => {},
+            }
+        }
+
+        None
+    }
+
+    fn eval_region (&self, region: &str) -> Option<String> {
+        let mut p = Exp::new(region, self);
+        let v = p.ternary()?;
+        p.skip_ws();
+
+        // NOTE: a lone unresolved name is likely literal text, eg: 'Press {enter}'
+        if p.done() && p.unresolved.as_deref() == Some(region.trim()) { return None }
+
+        let spec = {
+            if p.done() { None }
+            else if p.eat('|') { return Some(self.select_form(&v, &p.rest())) }
+            else if p.eat(':') { Some(p.rest()) }
+            else { return None }
+        };
+
+        let spec = match spec {
+            Some(ref spec) => Spec::parse(spec)?,
+            None => Spec::default(),
+        };
+
+        Some(spec.apply(&v))
+    }
+
+    /// Picks a plural or select form, eg: {coins|coin|coins} or {gender|male:he|female:she|other:they}
+    ///
+    /// Forms are keyed when each starts with a key and colon, otherwise numbers
+    /// pick their form by position in the plural categories. Keys match against
+    /// exact numbers (eg: '=0'), plural categories, or the value's text, with
+    /// 'other' as the fallback. The chosen form is formatted in turn
+    fn select_form (&self, v: &Var, forms: &str) -> String {
+        let forms = split_forms(forms);
+        let keyed: Vec<(&str,&str)> = forms.iter().filter_map(|f| form_key(f)).collect();
+
+        let num = match *v {
+            Var::Int(n) => Some(n as Float),
+            Var::Num(n) => Some(n),
+            _ => None,
+        };
+        let category = num.map(|n| {
+            match self.plural {
+                Some(rules) => rules.select(n),
+                None => PluralRules::english().select(n),
+            }
+        });
+
+        let form = {
+            if keyed.len() == forms.len() {
+                let text = v.to_string();
+                let exact = num.map(|n| format!("={}", n));
+                keyed.iter()
+                    .find(|&&(k,_)| Some(k.to_owned()) == exact)
+                    .or_else(|| keyed.iter().find(|&&(k,_)| category.is_some() && Plural::parse(k) == category))
+                    .or_else(|| keyed.iter().find(|&&(k,_)| num.is_none() && k == text))
+                    .or_else(|| keyed.iter().find(|&&(k,_)| k == "other"))
+                    .map(|&(_,f)| f)
+            }
+            else if let Some(category) = category {
+                let idx = match self.plural {
+                    Some(rules) => rules.categories.iter().position(|&c| c == category),
+                    None => PluralRules::english().categories.iter().position(|&c| c == category),
+                };
+                let idx = idx.unwrap_or(forms.len() - 1);
+                forms.get(idx).or_else(|| forms.last()).map(|f| &f[..])
+            }
+            else if *v == Var::Bool(false) || *v == Var::Nil { forms.get(1).map(|f| &f[..]) }
+            else { forms.first().map(|f| &f[..]) }
+        };
+
+        match form {
+            Some(form) => self.format(form),
+            None => String::new(),
+        }
+    }
+
+    /// Formats the older backtick style, eg: "hi `player.name"
+    ///
+    /// Symbols end at the next space or backtick
+    fn format_legacy (&self, s: &str) -> String {
+        let mut fs = String::new();
+        let mut sym = String::new();
+        let mut in_sym = false;
+
+        for c in s.chars() {
+            if (c == ' ' || c == '`') && !sym.is_empty() {
+                if let Some(v) = self.lookup(&sym) {
+                    fs.push_str(&v.to_string());
+                }
+                else {
+                    fs.push_str(&sym); //push as non-ref sym again
+                    // NOTE: we should consider failing silently (dont push)
+                }
+
+                if c == '`' { in_sym = true; }
+                else {
+                    in_sym = false;
+                    sym.clear();
+                    fs.push(' ');
+                }
+            }
+            else if c == '`' { in_sym = true; }
+            else {
+                if in_sym { sym.push(c); }
+                else { fs.push(c); }
+            }
+        }
+
+        if !sym.is_empty() {
+            if let Some(v) = self.lookup(&sym) {
+                fs.push_str(&v.to_string());
+            }
+            else {
+                fs.push_str(&sym);
+            }
+        }
+
+        fs
+    }
+
+    fn lookup (&self, sym: &str) -> Option<Var> {
+        match self.session {
+            Some(session) => Evaluator::resolve(sym, self.logic, self.def, session),
+            None => Evaluator::resolve(sym, self.logic, self.def, &Session::default()),
+        }
+    }
+}
+
+/// Nil and false are falsey, everything else is truthy
+fn is_true (v: &Var) -> bool {
+    match *v {
+        Var::Bool(b) => b,
+        Var::Nil => false,
+        _ => true,
+    }
+}
+
+/// Recursive descent parser for expressions within a region
+///
+/// Evaluates while parsing, returning None on any syntax error
+struct Exp<'i,'a: 'i> {
+    chars: Vec<char>,
+    pos: usize,
+    interp: &'i Interp<'a>,
+    /// Last name that didn't resolve, read as nil
+    unresolved: Option<String>,
+}
+
+impl<'i,'a> Exp<'i,'a> {
+    fn new (s: &str, interp: &'i Interp<'a>) -> Exp<'i,'a> {
+        Exp { chars: s.chars().collect(), pos: 0, interp, unresolved: None }
+    }
+
+    fn done (&self) -> bool { self.pos >= self.chars.len() }
+
+    fn peek (&self) -> Option<char> { self.chars.get(self.pos).cloned() }
+
+    fn rest (&self) -> String { self.chars[self.pos..].iter().collect() }
+
+    fn skip_ws (&mut self) {
+        while let Some(c) = self.peek() {
+            if c.is_whitespace() { self.pos += 1; }
+            else { break }
+        }
+    }
+
+    /// Consumes the exact operator if it is next
+    fn eat_op (&mut self, op: &str) -> bool {
+        self.skip_ws();
+        let len = op.chars().count();
+        if self.pos + len > self.chars.len() { return false }
+
+        let next: String = self.chars[self.pos..self.pos+len].iter().collect();
+        if next == op {
+            self.pos += len;
+            return true
+        }
+
+        false
+    }
+
+    fn eat (&mut self, c: char) -> bool {
+        self.skip_ws();
+        if self.peek() == Some(c) {
+            self.pos += 1;
+            return true
+        }
+
+        false
+    }
+
+    fn ternary (&mut self) -> Option<Var> {
+        let cond = self.or()?;
+        if self.eat('?') {
+            let a = self.ternary()?;
+            if !self.eat(':') { return None }
+            let b = self.ternary()?;
+
+            if is_true(&cond) { Some(a) }
+            else { Some(b) }
+        }
+        else { Some(cond) }
+    }
+
+    fn or (&mut self) -> Option<Var> {
+        let mut v = self.and()?;
+        while self.eat_op("||") {
+            let r = self.and()?;
+            v = Var::Bool(is_true(&v) || is_true(&r));
+        }
+
+        Some(v)
+    }
+
+    fn and (&mut self) -> Option<Var> {
+        let mut v = self.cmp()?;
+        while self.eat_op("&&") {
+            let r = self.cmp()?;
+            v = Var::Bool(is_true(&v) && is_true(&r));
+        }
+
+        Some(v)
+    }
+
+    fn cmp (&mut self) -> Option<Var> {
+        let v = self.add()?;
+        for op in &["==","!=","<=",">=","<",">"] {
+            if self.eat_op(op) {
+                let r = self.add()?;
+                let ord = match (&v, &r) {
+                    (Var::String(a), Var::String(b)) => Some(a.cmp(b)),
+                    _ => v.cmp_num(&r),
+                };
+
+                let b = match *op {
+                    "==" => v == r,
+                    "!=" => v != r,
+                    "<=" => ord == Some(Ordering::Less) || ord == Some(Ordering::Equal),
+                    ">=" => ord == Some(Ordering::Greater) || ord == Some(Ordering::Equal),
+                    "<" => ord == Some(Ordering::Less),
+                    _ => ord == Some(Ordering::Greater),
+                };
+
+                return Some(Var::Bool(b))
+            }
+        }
+
+        Some(v)
+    }
+
+    fn add (&mut self) -> Option<Var> {
+        let mut v = self.mul()?;
+        loop {
+            let m = {
+                if self.eat('+') { Mut::Add }
+                else if self.eat('-') { Mut::Sub }
+                else { break }
+            };
+
+            let r = self.mul()?;
+            v = match (m, v, r) {
+                (Mut::Add, Var::String(mut a), r) => { a.push_str(&r.to_string()); Var::String(a) },
+                (Mut::Add, l, Var::String(b)) => Var::String(l.to_string() + &b),
+                (m, l, r) => m.apply_num(&l, &r)?,
+            };
+        }
+
+        Some(v)
+    }
+
+    fn mul (&mut self) -> Option<Var> {
+        let mut v = self.unary()?;
+        loop {
+            let m = {
+                if self.eat('*') { Mut::Mul }
+                else if self.eat('/') { Mut::Div }
+                else { break }
+            };
+
+            let r = self.unary()?;
+            v = m.apply_num(&v, &r)?;
+        }
+
+        Some(v)
+    }
+
+    fn unary (&mut self) -> Option<Var> {
+        if self.eat('!') {
+            let v = self.unary()?;
+            return Some(Var::Bool(!is_true(&v)))
+        }
+        if self.eat('-') {
+            let v = self.unary()?;
+            return Mut::Sub.apply_num(&Var::Int(0), &v)
+        }
+
+        self.primary()
+    }
+
+    fn primary (&mut self) -> Option<Var> {
+        self.skip_ws();
+        let c = self.peek()?;
+
+        if c == '(' {
+            self.pos += 1;
+            let v = self.ternary()?;
+            if !self.eat(')') { return None }
+            return Some(v)
+        }
+
+        if c == '\'' || c == '"' {
+            self.pos += 1;
+            let mut s = String::new();
+            loop {
+                let n = self.peek()?;
+                self.pos += 1;
+                if n == c { break }
+                s.push(n);
+            }
+
+            return Some(Var::String(s))
+        }
+
+        if c.is_ascii_digit() {
+            let mut s = String::new();
+            while let Some(n) = self.peek() {
+                let is_frac = n == '.' &&
+                    self.chars.get(self.pos+1).map_or(false, |d| d.is_ascii_digit());
+                if n.is_ascii_digit() || is_frac { s.push(n); self.pos += 1; }
+                else { break }
+            }
+
+            if let Ok(n) = s.parse::<i64>() { return Some(Var::Int(n)) }
+            return s.parse::<Float>().ok().map(Var::Num)
+        }
+
+        if is_sym_char(c) {
+            let mut s = String::new();
+            while let Some(n) = self.peek() {
+                // dashes are kept within names, eg: exit-town, but not as 'a - b'
+                let in_name = (n == '-' || n == '.') && !s.is_empty() &&
+                    self.chars.get(self.pos+1).map_or(false, |&d| is_sym_char(d));
+                if is_sym_char(n) || in_name { s.push(n); self.pos += 1; }
+                else { break }
+            }
+
+            return match &s[..] {
+                "true" => Some(Var::Bool(true)),
+                "false" => Some(Var::Bool(false)),
+                "nil" => Some(Var::Nil),
+                _ => match self.interp.lookup(&s) {
+                    Some(v) => Some(v),
+                    None => {
+                        self.unresolved = Some(s);
+                        Some(Var::Nil)
+                    },
+                },
+            }
+        }
+
+        None
+    }
+}
+
+/// Splits forms on each '|', skipping over any nested regions
+fn split_forms (s: &str) -> Vec<String> {
+    let mut forms = vec![];
+    let mut form = String::new();
+    let mut depth = 0;
+
+    for c in s.chars() {
+        match c {
+            '{' => { depth += 1; },
+            '}' => { depth -= 1; },
+            '|' if depth == 0 => {
+                forms.push(form);
+                form = String::new();
+                continue
+            },
+            _ => {},
+        }
+
+        form.push(c);
+    }
+
+    forms.push(form);
+    forms
+}
+
+/// Splits a keyed form, eg: 'one:coin' or '=0:no coins'
+fn form_key (form: &str) -> Option<(&str,&str)> {
+    let form = form.trim_start();
+    let idx = form.find(':')?;
+    let key = &form[..idx];
+
+    let valid = {
+        if let Some(n) = key.strip_prefix('=') {
+            !n.is_empty() && n.parse::<Float>().is_ok()
+        }
+        else { !key.is_empty() && key.chars().all(is_sym_char) }
+    };
+
+    if valid { Some((key, &form[idx+1..])) }
+    else { None }
+}
+
+fn is_sym_char (c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+/// Largest width or precision a format spec may ask for
+const SPEC_MAX: usize = 1024;
+
+/// Format specifier, eg: '{gold:,}' or '{hp:>6.1}'
+///
+/// Written as [[fill]align][0][width][,][.precision], where align is one of '<' '>' '^'
+#[derive(Debug,Default,PartialEq)]
+struct Spec {
+    fill: Option<char>,
+    align: Option<char>,
+    zero: bool,
+    width: usize,
+    thousands: bool,
+    precision: Option<usize>,
+}
+
+impl Spec {
+    fn parse (s: &str) -> Option<Spec> {
+        let chars: Vec<char> = s.trim().chars().collect();
+        let mut spec = Spec::default();
+        let mut i = 0;
+
+        let is_align = |c: Option<&char>| c.map_or(false, |c| "<>^".contains(*c));
+        if chars.len() > 1 && is_align(chars.get(1)) {
+            spec.fill = Some(chars[0]);
+            spec.align = Some(chars[1]);
+            i = 2;
+        }
+        else if is_align(chars.first()) {
+            spec.align = Some(chars[0]);
+            i = 1;
+        }
+
+        if chars.get(i) == Some(&'0') {
+            spec.zero = true;
+            i += 1;
+        }
+
+        let mut width = String::new();
+        while let Some(&c) = chars.get(i) {
+            if !c.is_ascii_digit() { break }
+            width.push(c);
+            i += 1;
+        }
+        if !width.is_empty() { spec.width = width.parse().ok()?; }
+
+        if chars.get(i) == Some(&',') {
+            spec.thousands = true;
+            i += 1;
+        }
+
+        if chars.get(i) == Some(&'.') {
+            let mut prec = String::new();
+            i += 1;
+            while let Some(&c) = chars.get(i) {
+                if !c.is_ascii_digit() { break }
+                prec.push(c);
+                i += 1;
+            }
+            spec.precision = Some(prec.parse().ok()?);
+        }
+
+        if i < chars.len() { return None }
+
+        // NOTE: specs may come from translations, so they're bounded
+        if spec.width > SPEC_MAX || spec.precision.map_or(false, |p| p > SPEC_MAX) { return None }
+        Some(spec)
+    }
+
+    fn apply (&self, v: &Var) -> String {
+        let is_num = matches!(*v, Var::Int(_) | Var::Num(_));
+
+        let mut s = match (v, self.precision) {
+            (&Var::Nil, _) => String::new(),
+            (&Var::Int(n), Some(p)) => format!("{:.*}", p, n as f64),
+            (&Var::Num(n), Some(p)) => format!("{:.*}", p, n),
+            _ => v.to_string(),
+        };
+
+        let mut sign = String::new();
+        if is_num {
+            if s.starts_with('-') { sign.push(s.remove(0)); }
+            if self.thousands { s = group_thousands(&s); }
+        }
+
+        let len = sign.chars().count() + s.chars().count();
+        if len >= self.width { return sign + &s }
+        let pad = self.width - len;
+
+        if self.zero && self.align.is_none() && is_num {
+            return sign + &"0".repeat(pad) + &s
+        }
+
+        let s = sign + &s;
+        let fill = self.fill.unwrap_or(' ').to_string();
+        let align = self.align.unwrap_or(if is_num { '>' } else { '<' });
+        match align {
+            '>' => fill.repeat(pad) + &s,
+            '^' => fill.repeat(pad/2) + &s + &fill.repeat(pad - pad/2),
+            _ => s + &fill.repeat(pad),
+        }
+    }
+}
+
+/// Inserts commas between each group of three digits, eg: 1234567.5 -> 1,234,567.5
+fn group_thousands (s: &str) -> String {
+    let (int, frac) = match s.find('.') {
+        Some(i) => s.split_at(i),
+        None => (s, ""),
+    };
+
+    let mut grouped = String::new();
+    for (i,c) in int.chars().enumerate() {
+        if i > 0 && (int.len() - i) % 3 == 0 { grouped.push(','); }
+        grouped.push(c);
+    }
+
+    grouped + frac
+}
@@ -6,3 +6,12 @@ pub mod logic;
 pub mod env;
 pub mod def;
 pub mod fun;
+pub mod interp;
+pub mod locale;
+pub mod po;
+pub mod event;
+pub mod session;
+pub mod rng;
+pub mod input;
+pub mod command;
+pub mod bark;
@@ -0,0 +1,239 @@
+use std::collections::HashMap;
+
+use var::Var;
+use source::{Src,Next};
+use parse::{Parser,Block,SrcBlock,Map};
+
+/// Builds a stable line ID from the node name and a hash of the source text
+///
+/// Eg: 'store-3f2a9c1e', the same text within the same node shares an ID
+pub fn line_id (node: &str, text: &str) -> String {
+    // NOTE: FNV-1a, as std hashers may change between releases
+    let mut hash: u32 = 0x811c_9dc5;
+    for b in text.bytes() {
+        hash ^= b as u32;
+        hash = hash.wrapping_mul(0x0100_0193);
+    }
+
+    format!("{}-{:08x}", node, hash)
+}
+
+/// A translatable string found in source
+#[derive(Debug,PartialEq,Clone)]
+pub struct Line {
+    pub id: String,
+    pub text: String,
+    /// Node the string was found in
+    pub node: String,
+    /// Statement index within the node
+    pub idx: usize,
+    /// Source line number of the statement
+    pub line: usize,
+}
+
+impl Line {
+    /// Lists the translatable strings of a single statement, with their line IDs
+    ///
+    /// These are strings emitted by emit/say/if/or and variation statements, followed by any select keys.
+    /// Explicit #line:some-id tags name the emitted strings in order, with any
+    /// strings past the last tag numbered after the first, eg: some-id.1
+    pub fn statement (b: &SrcBlock, idx: usize) -> Vec<Line> {
+        let mut lines = vec![];
+        let (vars, next) = match b.src.get(idx) {
+            Some(Src::Emit(vars)) | Some(Src::Vary(_, vars)) => (&vars[..], None),
+            Some(Src::Say(_, text)) => (::std::slice::from_ref(text), None),
+            Some(Src::If(_, vars, next)) |
+            Some(Src::Or(vars, next)) => (&vars[..], next.as_ref()),
+            Some(Src::Next(next)) => (&[][..], Some(next)),
+            _ => return lines,
+        };
+
+        let meta = b.meta.get(idx);
+        let line = meta.map_or(0, |m| m.line);
+        let tagged = meta.map_or(&[][..], |m| &m.ids[..]);
+
+        let mut texts: Vec<(String, &str)> = vec![];
+        for v in vars {
+            if let Var::String(ref s) = *v {
+                let id = match (tagged.get(texts.len()), tagged.first()) {
+                    (Some(id), _) => id.clone(),
+                    (None, Some(id)) => format!("{}.{}", id, texts.len()),
+                    (None, None) => line_id(&b.name, s),
+                };
+                texts.push((id, s));
+            }
+        }
+        if let Some(Next::Select(select)) = next {
+            texts.extend(select.map.keys().map(|k| (line_id(&b.name, k), &k[..])));
+        }
+
+        for (id, text) in texts {
+            lines.push(Line {
+                id,
+                text: text.to_owned(),
+                node: b.name.clone(),
+                idx,
+                line,
+            });
+        }
+
+        lines
+    }
+
+    /// Lists every translatable string in a node, in statement order
+    pub fn collect (b: &SrcBlock) -> Vec<Line> {
+        let mut lines = vec![];
+        for idx in 0..b.src.len() {
+            lines.extend(Line::statement(b, idx));
+        }
+
+        lines
+    }
+}
+
+/// Writes #line tags for each untagged statement with emitted strings
+///
+/// A tag is written for each string, with its generated ID, so existing
+/// translations still apply, and afterwards they no longer change with the text.
+/// Tags are written at the end of the statement's first line, or after
+/// a quoted string when it spans lines
+pub fn write_line_ids (src: &str) -> Result<String,&'static str> {
+    let mut ids: HashMap<usize,Vec<String>> = HashMap::new(); // keyed by line number
+    for block in Parser::parse_blocks(src)?.iter() {
+        if let Block::Src(ref b) = *block {
+            for (idx, meta) in b.meta.iter().enumerate() {
+                if !meta.ids.is_empty() { continue }
+
+                let texts = match b.src[idx] {
+                    Src::Emit(ref vars) | Src::Vary(_, ref vars) |
+                    Src::If(_, ref vars, _) | Src::Or(ref vars, _) => {
+                        vars.iter().filter(|v| matches!(**v, Var::String(_))).count()
+                    },
+                    Src::Say(_, ref text) => matches!(*text, Var::String(_)) as usize,
+                    _ => 0,
+                };
+
+                // NOTE: select keys follow the emitted strings, and keep their hashed IDs
+                if texts > 0 {
+                    let lines = Line::statement(b, idx).into_iter().take(texts);
+                    ids.insert(meta.line, lines.map(|l| l.id).collect());
+                }
+            }
+        }
+    }
+
+    let mut out = String::new();
+    let mut line = 1;
+    let mut pending = None;
+    let mut in_string = false;
+    let mut in_comment = false;
+
+    for c in src.chars() {
+        if c == '\n' {
+            if let Some(id) = ids.remove(&line) { pending = Some(id); }
+            
+            if !in_string {
+                if let Some(ids) = pending.take() {
+                    let cr = out.ends_with('\r');
+                    if cr { let _ = out.pop(); }
+                    
+                    let len = out.trim_end().len();
+                    out.truncate(len);
+                    for id in ids {
+                        out.push_str(" #line:");
+                        out.push_str(&id);
+                    }
+                    if cr { out.push('\r'); }
+                }
+                
+                in_comment = false;
+            }
+            
+            line += 1;
+        }
+        else if c == '"' && !in_comment { in_string = !in_string; }
+        else if c == '#' && !in_string { in_comment = true; }
+
+        out.push(c);
+    }
+
+    for id in pending.or_else(|| ids.remove(&line)).unwrap_or_default() {
+        out.push_str(" #line:");
+        out.push_str(&id);
+    }
+
+    Ok(out)
+}
+
+/// String table for a single locale, keyed by line ID
+#[derive(Debug,PartialEq,Clone)]
+pub struct Locale {
+    pub name: String,
+    pub lines: HashMap<String,String>,
+}
+
+impl Locale {
+    pub fn new (name: &str) -> Locale {
+        Locale { name: name.to_owned(), lines: HashMap::new() }
+    }
+
+    /// Parses a locale table, each entry is a line ID followed by the quoted text
+    ///
+    /// Eg: store-3f2a9c1e "Bonjour, {player.name}"
+    /// Quoted text may span multiple lines, and '#' starts a comment
+    pub fn parse (name: &str, src: &str) -> Result<Locale,&'static str> {
+        let mut locale = Locale::new(name);
+        let mut id = String::new();
+        let mut text = String::new();
+        let mut in_string = false;
+        let mut in_comment = false;
+
+        for c in src.chars() {
+            if in_string {
+                if c == '"' {
+                    if id.is_empty() { return Err("Locale text is missing its line ID") }
+                    locale.lines.insert(id.clone(), text.clone());
+                    id.clear();
+                    text.clear();
+                    in_string = false;
+                }
+                else { text.push(c); }
+            }
+            else if in_comment {
+                if c == '\n' { in_comment = false; }
+            }
+            else if c == '#' { in_comment = true; }
+            else if c == '"' { in_string = true; }
+            else if c.is_whitespace() {
+                if c == '\n' && !id.is_empty() { return Err("Locale line ID is missing its text") }
+            }
+            else { id.push(c); }
+        }
+
+        if in_string { return Err("Locale text is missing its closing quote") }
+        if !id.is_empty() { return Err("Locale line ID is missing its text") }
+
+        Ok(locale)
+    }
+
+    pub fn get (&self, id: &str) -> Option<&str> {
+        self.lines.get(id).map(|s| &s[..])
+    }
+
+    pub fn insert (&mut self, id: String, text: String) -> Option<String> {
+        self.lines.insert(id, text)
+    }
+
+    /// Swaps in translated text, returns None when there is no translation
+    pub fn translate (&self, node: &str, text: &str) -> Option<String> {
+        self.get(&line_id(node, text)).map(|s| s.to_owned())
+    }
+
+    /// Translates each select key, keeping the original text when missing
+    pub fn translate_map (&self, node: &str, map: &Map) -> Map {
+        map.iter().map(|(k,v)| {
+            let k = self.translate(node, k).unwrap_or_else(|| k.clone());
+            (k, v.clone())
+        }).collect()
+    }
+}
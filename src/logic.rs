@@ -1,9 +1,11 @@
-use var::Var;
+use var::{Var,Float};
 use parse::IR;
-use eval::Evaluator;
+use eval::{Eval,Evaluator};
 use def::Def;
+use session::Session;
 
 use std::collections::HashMap;
+use std::cmp::Ordering;
 
 /// Expect Types for Composites
 #[derive(Debug,PartialEq, Clone, Copy)]
@@ -35,21 +37,35 @@ pub enum Logic {
     GT(Var,Var),
     /// Lesser Than
     LT(Var,Var),
+    /// Equal To, eg: fact.who == guard
+    ///
+    /// Symbols that don't resolve compare as their text
+    EQ(Var,Var),
 
     /// Boolean: True
     Is(String),
     /// Boolean: False
     IsNot(String),
 
+    /// Value is set and not nil, eg: has_name exists player.name
+    Exists(String),
+    /// Value is nil or missing, eg: no_name is_nil player.name
+    IsNil(String),
+
+    /// Random roll, true with the given odds, eg: crit chance 0.25
+    ///
+    /// Rolled again each time it's checked
+    Chance(Var),
+
     /// A composite logic type to group logic statements together
     Composite(Expect, Vec<String>),
 }
 
 pub type Logics = HashMap<String,LogicFn>;
-pub struct LogicFn(Box<Fn(&Def,&Logics) -> Option<bool> + Send>);
+pub struct LogicFn(Box<Fn(&Def,&Logics,&Session) -> Option<bool> + Send>);
 impl LogicFn {
-    pub fn run(&self, def: &Def, logic: &Logics) -> Option<bool> {
-        self.0(def, logic)
+    pub fn run(&self, def: &Def, logic: &Logics, session: &Session) -> Option<bool> {
+        self.0(def, logic, session)
     }
 }
 
@@ -93,6 +109,17 @@ impl Logic {
                 Ok(Logic::Is(exp))
             }
         }
+        else if len == 2 {
+            let lookup: String = exp.pop().unwrap().into();
+            let test: String = exp.pop().unwrap().into();
+
+            match &test[..] {
+                "exists" => Ok(Logic::Exists(lookup)),
+                "is_nil" => Ok(Logic::IsNil(lookup)),
+                "chance" => Ok(Logic::Chance(Var::parse(IR::Sym(lookup))?)),
+                _ => Err("Invalid Logic Syntax"),
+            }
+        }
         else if len == 3 {
             let var = exp.pop().unwrap();
             let var = try!(Var::parse(var));
@@ -107,6 +134,9 @@ impl Logic {
             else if sym == "<" {
                 Ok(Logic::LT(key,var))
             }
+            else if sym == "==" {
+                Ok(Logic::EQ(key,var))
+            }
             else { Err("Invalid Logic Syntax") }
         }
         else { Err("Unbalanced Logic Syntax") }
@@ -117,12 +147,13 @@ impl Logic {
         match self {
             &Logic::Is(ref lookup) => {
                 let lookup = lookup.clone();
-                let lfn = Box::new(move |data: &Def, logic: &Logics| {
-                    if let Some(r) = Evaluator::resolve(&lookup, logic, data) {
+                let lfn = Box::new(move |data: &Def, logic: &Logics, session: &Session| {
+                    if let Some(r) = Evaluator::resolve(&lookup, logic, data, session) {
                         match r {
                             Var::Bool(v) => {
                                  Some(v)
                             },
+                            Var::Nil => { Some(false) },
                             _ => { //if exists?
                                 Some(true)
                             },
@@ -135,12 +166,13 @@ impl Logic {
             },
             &Logic::IsNot(ref lookup) => { //inverse state
                 let lookup = lookup.clone();
-                let lfn = Box::new(move |data: &Def, logic: &Logics| {
-                    if let Some(r) = Evaluator::resolve(&lookup, logic, data) {
+                let lfn = Box::new(move |data: &Def, logic: &Logics, session: &Session| {
+                    if let Some(r) = Evaluator::resolve(&lookup, logic, data, session) {
                         match r {
                             Var::Bool(v) => {
                                 Some(!v)
                             },
+                            Var::Nil => { Some(true) },
                             _ => {
                                 Some(false)
                             },
@@ -151,16 +183,45 @@ impl Logic {
 
                 LogicFn(lfn)
             },
+            &Logic::Exists(ref lookup) | &Logic::IsNil(ref lookup) => {
+                let lookup = lookup.clone();
+                let exists = matches!(*self, Logic::Exists(_));
+                let lfn = Box::new(move |data: &Def, logic: &Logics, session: &Session| {
+                    let is_set = match Evaluator::resolve(&lookup, logic, data, session) {
+                        Some(Var::Nil) | None => false,
+                        Some(_) => true,
+                    };
+
+                    Some(is_set == exists)
+                });
+
+                LogicFn(lfn)
+            },
+
+            Logic::Chance(odds) => {
+                let odds = odds.clone();
+                let lfn = Box::new(move |data: &Def, _logic: &Logics, session: &Session| {
+                    let odds = match session.get_number(&odds, data) {
+                        Ok(Var::Num(n)) => n,
+                        Ok(Var::Int(n)) => n as Float,
+                        _ => return None,
+                    };
+
+                    Some(session.rng.float() < odds)
+                });
+
+                LogicFn(lfn)
+            },
 
             &Logic::GT(ref left, ref right) => {
                 let left = left.clone();
                 let right = right.clone();
-                let lfn = Box::new(move |data: &Def, _logic: &Logics| {
-                    let right = Var::get_num(&right,data);
-                    let left = Var::get_num(&left,data);
+                let lfn = Box::new(move |data: &Def, _logic: &Logics, session: &Session| {
+                    let right = session.get_number(&right,data);
+                    let left = session.get_number(&left,data);
                 
-                    if left.is_ok() && right.is_ok() {
-                        Some(left.unwrap() > right.unwrap())
+                    if let (Ok(left), Ok(right)) = (left, right) {
+                        Some(left.cmp_num(&right) == Some(Ordering::Greater))
                     }
                     else { None }
                 });
@@ -170,32 +231,54 @@ impl Logic {
             &Logic::LT(ref left, ref right) => {
                 let left = left.clone();
                 let right = right.clone();
-                let lfn = Box::new(move |data: &Def, _logic: &Logics| {
-                    let right = Var::get_num(&right,data);
-                    let left = Var::get_num(&left,data);
+                let lfn = Box::new(move |data: &Def, _logic: &Logics, session: &Session| {
+                    let right = session.get_number(&right,data);
+                    let left = session.get_number(&left,data);
                     
-                    if left.is_ok() && right.is_ok() {
-                        Some(left.unwrap() < right.unwrap())
+                    if let (Ok(left), Ok(right)) = (left, right) {
+                        Some(left.cmp_num(&right) == Some(Ordering::Less))
                     }
                     else { None }
                 });
 
                 LogicFn(lfn)
             },
+            Logic::EQ(left, right) => {
+                let left = left.clone();
+                let right = right.clone();
+                let lfn = Box::new(move |data: &Def, logic: &Logics, session: &Session| {
+                    // NOTE: a symbol held in def compares as written, eg: a fact passed as Var::Sym
+                    let get = |v: &Var| match *v {
+                        Var::Sym(ref s) => Evaluator::resolve(s, logic, data, session)
+                            .or_else(|| data.get_last(s).map(|(v, _)| v))
+                            .unwrap_or_else(|| v.clone()),
+                        _ => v.clone(),
+                    };
+                    let (left, right) = (get(&left), get(&right));
+
+                    match left.cmp_num(&right) {
+                        Some(o) => Some(o == Ordering::Equal),
+                        None => Some(left.to_string() == right.to_string()),
+                    }
+                });
+
+                LogicFn(lfn)
+            },
             &Logic::Composite(x, ref lookups) => {
                 let lookups = lookups.clone();
-                let lfn = Box::new(move |data: &Def, logic: &Logics| {
+                let lfn = Box::new(move |data: &Def, logic: &Logics, session: &Session| {
                     // track if any lookups are false or true
                     let mut comp_true = false;
                     let mut comp_false = false;
                     
                     for lookup in lookups.iter() {
-                        if let Some(val) = Evaluator::resolve(lookup, logic, data) {
+                        if let Some(val) = Evaluator::resolve(lookup, logic, data, session) {
                             match val {
                                 Var::Bool(v) => {
                                     if v { comp_true = true; }
                                     else { comp_false = true; }
                                 },
+                                Var::Nil => { comp_false = true; },
                                 _ => { comp_true = lookup != &val.to_string(); }
                             }
                         }
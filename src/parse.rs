@@ -16,8 +16,181 @@ pub struct SrcBlock {
     pub or_valid: bool,
 
     pub logic: HashMap<String,LogicFn>,
+
+    /// Source details for each statement, indexed alongside src
+    pub meta: Vec<SrcMeta>,
+
+    /// Tags on the node's header line, eg: shop #portrait:left
+    pub tags: Vec<String>,
+
+    /// Entry condition, checked each time the node is entered
+    pub guard: Option<Guard>,
+
+    /// Marks the node as a storylet, see Evaluator::storylets
+    pub storylet: Option<Storylet>,
+
+    /// Marks the node as a bark rule, see bark::Barks
+    pub bark: Option<Bark>,
+
+    /// Signal the node handles, see Evaluator::signal
+    pub on: Option<Signal>,
+}
+
+/// Node entry condition, eg: shop [requires has_money else broke_node]
+#[derive(Debug,PartialEq,Clone)]
+pub struct Guard {
+    /// Logic in the node, or a def var, that must be true to enter
+    pub requires: String,
+    /// Node to head to instead, otherwise entry is refused
+    pub fallback: Option<String>,
+}
+
+/// Storylet details, eligible nodes are picked from by the host
+///
+/// eg: market [storylet priority 5 repeatable requires market_open]
+#[derive(Debug,PartialEq,Clone)]
+pub struct Storylet {
+    /// Number or def var, higher priorities are listed first
+    pub priority: Var,
+    /// Stays eligible after being visited
+    pub repeatable: bool,
+}
+
+/// Bark rule details, the node's leading logic statements are the rule's criteria
+///
+/// eg: guard_hurt [bark cooldown 30]
+#[derive(Debug,PartialEq,Clone,Copy,Default)]
+pub struct Bark {
+    /// Time before the rule can match again, in the host's time units
+    pub cooldown: u64,
+}
+
+/// Signal a node handles, eg: forest_intro [on enter_area "forest" priority 2]
+#[derive(Debug,PartialEq,Clone)]
+pub struct Signal {
+    pub name: String,
+    /// Leading args a signal must match, any args match when empty
+    pub args: Vec<Var>,
+    /// Number or def var, higher priority handlers run first
+    pub priority: Var,
+}
+
+/// Options from a node's header
+struct Header {
+    guard: Option<Guard>,
+    storylet: Option<Storylet>,
+    bark: Option<Bark>,
+    on: Option<Signal>,
+}
+
+const HEADER_OPTS: [&str; 8] = ["requires", "else", "storylet", "priority", "repeatable", "bark", "cooldown", "on"];
+
+/// Parses the options in a node's header, eg: [requires has_money else broke_node]
+fn parse_header (exps: Vec<IR>) -> Result<Header,&'static str> {
+    let mut requires = None;
+    let mut fallback = None;
+    let mut storylet = false;
+    let mut priority = None;
+    let mut repeatable = false;
+    let mut bark = false;
+    let mut cooldown = None;
+    let mut on = None;
+
+    let mut exps = exps.into_iter().peekable();
+    while let Some(opt) = exps.next() {
+        let opt: String = opt.into();
+        match &opt[..] {
+            "requires" => { requires = Some(exps.next().ok_or("Node guard is missing its logic")?.into()); },
+            "else" => { fallback = Some(exps.next().ok_or("Node guard is missing its fallback")?.into()); },
+            "storylet" => { storylet = true; },
+            "priority" => { priority = Some(Var::parse(exps.next().ok_or("Storylet is missing its priority")?)?); },
+            "repeatable" => { repeatable = true; },
+            "bark" => { bark = true; },
+            "on" => {
+                let name: String = exps.next().ok_or("Signal handler is missing its signal")?.into();
+                let mut args = vec![];
+                while let Some(arg) = exps.next_if(|ir| !matches!(*ir, IR::Sym(ref s) if HEADER_OPTS.contains(&&s[..]))) {
+                    args.push(Var::parse(arg)?);
+                }
+
+                on = Some((name, args));
+            },
+            "cooldown" => {
+                let n: String = exps.next().ok_or("Bark is missing its cooldown")?.into();
+                cooldown = Some(n.parse().map_err(|_| "Bark cooldown must be a whole number")?);
+            },
+            _ => return Err("Invalid node header"),
+        }
+    }
+
+    if requires.is_none() && fallback.is_some() { return Err("Node guard fallback needs a guard") }
+    if !storylet && repeatable { return Err("Storylet options need the storylet tag") }
+    if !storylet && on.is_none() && priority.is_some() { return Err("Priority needs the storylet or on tag") }
+    if !bark && cooldown.is_some() { return Err("Bark options need the bark tag") }
+
+    let guard = requires.map(|requires| Guard { requires, fallback });
+    let priority = priority.unwrap_or(Var::Int(0));
+    let storylet = if storylet { Some(Storylet { priority: priority.clone(), repeatable }) }
+    else { None };
+    let on = on.map(|(name, args)| Signal { name, args, priority });
+    let bark = if bark { Some(Bark { cooldown: cooldown.unwrap_or(0) }) }
+    else { None };
+    Ok(Header { guard, storylet, bark, on })
+}
+
+impl SrcBlock {
+    /// Tags on a single statement
+    pub fn line_tags (&self, idx: usize) -> &[String] {
+        self.meta.get(idx).map_or(&[], |m| &m.tags[..])
+    }
+
+    /// Checks the node's tags, see has_tag
+    pub fn has_tag (&self, tag: &str) -> bool {
+        has_tag(&self.tags, tag)
+    }
+}
+
+/// Source details for a single statement
+#[derive(Debug,PartialEq,Clone,Default)]
+pub struct SrcMeta {
+    /// Line number the statement starts on
+    pub line: usize,
+
+    /// Explicit line IDs, tagged in source with #line:some-id, one per emitted string in order
+    pub ids: Vec<String>,
+
+    /// Any other tags on the statement, eg: #mood:angry becomes 'mood:angry'
+    pub tags: Vec<String>,
+}
+
+/// Finds any tags within a comment, eg: "#line:abc123 #mood:angry"
+///
+/// The comment is passed without its leading '#'. Tags must directly follow a '#',
+/// and only a comment made up entirely of tags has any, so '# see #42' is plain text.
+/// Line IDs are the exception, found anywhere, eg: '# a comment #line:abc123'
+pub fn comment_tags (comment: &str) -> Vec<&str> {
+    let mut words = vec![];
+    for (i, word) in comment.split_whitespace().enumerate() {
+        // NOTE: the first word directly follows the comment's own '#'
+        if i == 0 && comment.starts_with(word) { words.push(Some(word)); }
+        else { words.push(word.strip_prefix('#').filter(|w| !w.is_empty())); }
+    }
+
+    if words.iter().all(|w| w.is_some()) { words.into_iter().flatten().collect() }
+    else { words.into_iter().flatten().filter(|w| w.starts_with("line:")).collect() }
 }
 
+/// Checks tags for a tag, or for a key when the tag is without a value
+///
+/// Eg: both 'mood' and 'mood:angry' are found in ["mood:angry"]
+pub fn has_tag (tags: &[String], tag: &str) -> bool {
+    tags.iter().any(|t| {
+        t == tag || (t.starts_with(tag) && t[tag.len()..].starts_with(':'))
+    })
+}
+
+// NOTE: blocks are few and short lived, so Src is left unboxed to keep matching on it simple
+#[allow(clippy::large_enum_variant)]
 #[derive(Debug,PartialEq)]
 pub enum Block {
     Src(SrcBlock),
@@ -65,8 +238,65 @@ impl From<Var> for IR {
 }
 
 
-/// Map object for Selects
-pub type Map = HashMap<String,Vec<Var>>;
+/// Map object for Selects, keeps entries in source order
+#[derive(Debug,PartialEq,Clone,Default)]
+pub struct Map(Vec<(String,Vec<Var>)>);
+
+impl Map {
+    pub fn new () -> Map {
+        Map(vec![])
+    }
+
+    /// Inserts an entry, replacing an existing key in place
+    pub fn insert (&mut self, key: String, vals: Vec<Var>) -> Option<Vec<Var>> {
+        if let Some(e) = self.0.iter_mut().find(|e| e.0 == key) {
+            return Some(::std::mem::replace(&mut e.1, vals))
+        }
+
+        self.0.push((key,vals));
+        None
+    }
+
+    pub fn get (&self, key: &str) -> Option<&Vec<Var>> {
+        self.0.iter().find(|e| e.0 == key).map(|e| &e.1)
+    }
+
+    pub fn contains_key (&self, key: &str) -> bool {
+        self.get(key).is_some()
+    }
+
+    pub fn iter (&self) -> impl Iterator<Item=(&String,&Vec<Var>)> {
+        self.0.iter().map(|e| (&e.0, &e.1))
+    }
+
+    pub fn keys (&self) -> impl Iterator<Item=&String> {
+        self.0.iter().map(|e| &e.0)
+    }
+
+    pub fn len (&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty (&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+impl ::std::iter::FromIterator<(String,Vec<Var>)> for Map {
+    fn from_iter<I: IntoIterator<Item=(String,Vec<Var>)>> (iter: I) -> Map {
+        let mut map = Map::new();
+        for (k,v) in iter { map.insert(k,v); }
+        map
+    }
+}
+
+impl IntoIterator for Map {
+    type Item = (String,Vec<Var>);
+    type IntoIter = ::std::vec::IntoIter<(String,Vec<Var>)>;
+    fn into_iter (self) -> Self::IntoIter {
+        self.0.into_iter()
+    }
+}
 
 pub struct Parser(Vec<Block>);
 
@@ -78,6 +308,11 @@ impl Deref for Parser {
 
 impl Parser {
     pub fn parse_blocks (src: &str) -> Result<Parser,&'static str> {
+        Parser::parse_blocks_from(src, 1)
+    }
+
+    /// Parses blocks, counting line numbers from the first line specified
+    pub fn parse_blocks_from (src: &str, first_line: usize) -> Result<Parser,&'static str> {
         let mut v = vec!();
         let mut exp = String::new();
         let mut exps: Vec<IR> = vec!();
@@ -92,17 +327,58 @@ impl Parser {
 
         
         let mut usyms = BTreeSet::new(); //unique set, remove dupes
+
+        let mut line = first_line;
+        let mut stmt_line = line; // line the current statement started on
+        let mut stmt_ids: Vec<String> = vec![]; // explicit line IDs for the current statement
+        let mut stmt_tags: Vec<String> = vec![];
+        let mut flushed_at: Option<usize> = None; // line of the last statement ended mid-line, eg: by a ']'
+        let mut comment = String::new();
                             
 
         for c in src.chars() {
+            if c == '\n' { line += 1; }
+            else if !c.is_whitespace() && !in_comment && !in_string && c != '#' &&
+                exps.is_empty() && map_ir.is_empty() && exp.trim().is_empty() {
+                stmt_line = line;
+            }
+            
             if !in_comment && !in_string {
                 if c == '[' { in_vec = true; continue }
                 else if c == ']' { in_vec = false; }
                 
             }
             
-            if c == '#' && !in_string { in_comment = true; continue }
-            else if  c == '\n' && in_comment && !in_string { in_comment = false; }
+            if c == '#' && !in_string {
+                if in_comment { comment.push(c); }
+                else { comment.clear(); }
+                
+                in_comment = true;
+                continue
+            }
+            else if  c == '\n' && in_comment && !in_string {
+                in_comment = false;
+                for tag in comment_tags(&comment) {
+                    if let Some(id) = tag.strip_prefix("line:") {
+                        stmt_ids.push(id.to_owned());
+                    }
+                    else { stmt_tags.push(tag.to_owned()); }
+                }
+
+                // tags after a statement that already ended on this line still belong to it
+                if flushed_at == Some(line - 1) && exps.is_empty() {
+                    if let Some(Block::Src(ref mut b)) = block {
+                        match b.meta.last_mut() {
+                            Some(m) => {
+                                m.tags.append(&mut stmt_tags);
+                                if m.ids.is_empty() { m.ids.append(&mut stmt_ids); }
+                            },
+                            None => { b.tags.append(&mut stmt_tags); },
+                        }
+                    }
+                }
+            }
+            else if in_comment { comment.push(c); }
 
             if c == '\n' && (in_vec || in_map) && !in_comment && !in_string { continue }
             
@@ -118,13 +394,14 @@ impl Parser {
                     else { exps.push(sym); }
                 }
                 
+                exp = String::new();
+                
                 if c == '}' && in_map && !in_comment{
                     in_map = false;
                     exps.push(IR::Map(map_ir));
                     map_ir = vec![];
+                    continue // statement may carry on past the map, eg: select fallbacks
                 }
-                
-                exp = String::new();
 
                 if exps.len() < 1 { continue }
                 
@@ -141,16 +418,25 @@ impl Parser {
                         block = Some(Block::Def(b));
                     }
                     else {
+                        let header = parse_header(exps.split_off(0))?;
+                        
                         let b = SrcBlock {
                             name: name,
                             src: vec!(),
                             idx: 0,
                             visited: false,
                             or_valid: false,
-                            logic: HashMap::new()
+                            logic: HashMap::new(),
+                            meta: vec!(),
+                            tags: stmt_tags.split_off(0),
+                            guard: header.guard,
+                            storylet: header.storylet,
+                            bark: header.bark,
+                            on: header.on,
                         };
                         
                         block = Some(Block::Src(b));
+                        flushed_at = if c == '\n' { None } else { Some(line) };
                     }
                     
                 }
@@ -192,8 +478,9 @@ impl Parser {
                         Some(Block::Def(ref mut b)) => {
                             let v = exps.pop().unwrap();
                             let r = try!(Var::parse(v));
-                            b.data.insert(exps.pop().unwrap().into(),
-                                         r);
+                            let key: String = exps.pop().unwrap().into();
+                            let keys: Vec<&str> = key.split_terminator('.').collect();
+                            b.set_path(&keys, r); // dotted keys build nested maps
                         },
                         Some(Block::Src(ref mut b)) => {
                             let mut srcs: Vec<Src> = vec![];
@@ -223,13 +510,22 @@ impl Parser {
                                 }
 
                                 
+                                let (ids, tags) = match src {
+                                    Src::Logic(_,_) => (vec![], vec![]), // NOTE: only the tagged statement itself takes the tags
+                                    _ => (stmt_ids.split_off(0), stmt_tags.split_off(0)),
+                                };
+                                
                                 b.src.push(src);
+                                b.meta.push(SrcMeta { line: stmt_line, ids, tags });
                             }
                         },
                         _ => {}
                     }
 
                     exps = vec!();
+                    stmt_ids.clear();
+                    stmt_tags.clear();
+                    flushed_at = if c == '\n' { None } else { Some(line) };
                 }
             }
             else if c == '"' && !in_comment {
@@ -261,9 +557,10 @@ impl Parser {
             else {
                 if c == '{' && !in_comment && !in_string {
                     in_map = true;
-                    // push previous symbol
-                    let sym = IR::Sym(exp.trim().to_owned());
-                    exps.push(sym);
+                    // push previous symbols, eg: a select's ticks
+                    for n in exp.split_whitespace() {
+                        exps.push(IR::Sym(n.to_owned()));
+                    }
                     exp.clear();
                 }
                 else if !in_comment {
@@ -302,7 +599,7 @@ impl Parser {
     ///
     /// Parsed using commas for variable sized maps
     pub fn parse_map (map_ir: IR) -> Result<Map,&'static str> {
-        let mut map: Map = HashMap::new(); // optionally unbounded val-lengths
+        let mut map = Map::new(); // optionally unbounded val-lengths
 
         match map_ir {
             IR::Map(mut exps) => {
@@ -316,8 +613,10 @@ impl Parser {
                         IR::Sym(mut s) => {
                             if s.chars().last() == Some(',') {
                                 let _ = s.pop();
-                                let var = Var::parse(IR::Sym(s))?;
-                                vals.push(var);
+                                if !s.is_empty() { // comma may trail a string
+                                    let var = Var::parse(IR::Sym(s))?;
+                                    vals.push(var);
+                                }
 
                                 map.insert(key,vals);
                                 vals = vec![];
@@ -358,6 +657,8 @@ pub struct StreamParser<S:Read> {
     size: usize,
     pub blocks: Vec<Block>,
     curr_block: String,
+    /// Line number the buffered leftovers start on
+    line: usize,
 }
 
 impl<S:Read> Iterator for StreamParser<S> {
@@ -379,6 +680,7 @@ impl<S:Read> StreamParser<S> {
             size: { if let Some(size) = size { size }
                     else { 1024 } },
             curr_block: "".to_owned(),
+            line: 1,
         }
     }
 
@@ -414,12 +716,13 @@ impl<S:Read> StreamParser<S> {
                         }
                         
                         if c == ';' {
-                            if let Ok(p) = Parser::parse_blocks(&block) {
+                            if let Ok(p) = Parser::parse_blocks_from(&block, self.line) {
                                 start = p.sink(&mut self.blocks);
                                 self.curr_block.clear();
                             }
                             else { return None } //end iteration when parsing fails
                             
+                            self.line += block.matches('\n').count();
                             block.clear();
                         }
                     }
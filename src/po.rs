@@ -0,0 +1,203 @@
+use env::Env;
+use locale::{Locale,Line};
+
+/// Writes a gettext template (.pot) for every translatable string in the environment
+///
+/// Each entry uses its line ID as the msgctxt, lists where it is found
+/// as node:line references, and notes any interpolated symbols
+pub fn write_pot (env: &Env) -> String {
+    let mut pot = String::new();
+    pot.push_str("msgid \"\"\n");
+    pot.push_str("msgstr \"\"\n");
+    pot.push_str("\"Content-Type: text/plain; charset=UTF-8\\n\"\n");
+
+    // lines sharing an ID are merged into a single entry
+    let mut entries: Vec<(Line, Vec<String>)> = vec![];
+    for line in env.lines() {
+        let reference = format!("{}:{}", line.node, line.line);
+        if let Some(&mut (_, ref mut refs)) = entries.iter_mut().find(|e| e.0.id == line.id) {
+            refs.push(reference);
+            continue
+        }
+
+        entries.push((line, vec![reference]));
+    }
+
+    for (line, refs) in entries {
+        pot.push('\n');
+
+        let symbols = placeholders(&line.text);
+        if !symbols.is_empty() {
+            pot.push_str(&format!("#. placeholders: {}\n", symbols.join(", ")));
+        }
+
+        pot.push_str(&format!("#: {}\n", refs.join(" ")));
+        pot.push_str(&format!("msgctxt \"{}\"\n", escape(&line.id)));
+        pot.push_str(&format!("msgid \"{}\"\n", escape(&line.text)));
+        pot.push_str("msgstr \"\"\n");
+    }
+
+    pot
+}
+
+/// Reads a translated .po file into a locale table
+///
+/// Entries are keyed by their msgctxt line ID, see write_pot.
+/// Untranslated and fuzzy entries are skipped, so they fall back to source text
+pub fn read_po (name: &str, src: &str) -> Result<Locale,&'static str> {
+    let mut locale = Locale::new(name);
+    let mut entry = Entry::default();
+    let mut field: Option<Field> = None;
+
+    for line in src.lines().map(|l| l.trim()) {
+        if line.is_empty() {
+            entry.finish(&mut locale);
+            field = None;
+            continue
+        }
+
+        if line.starts_with('#') {
+            if line.starts_with("#,") && line.contains("fuzzy") { entry.fuzzy = true; }
+            continue
+        }
+
+        let (next, text) = {
+            if let Some(rest) = line.strip_prefix("msgctxt ") { (Some(Field::Ctxt), rest) }
+            else if let Some(rest) = line.strip_prefix("msgid ") { (Some(Field::Id), rest) }
+            else if let Some(rest) = line.strip_prefix("msgstr ") { (Some(Field::Str), rest) }
+            else if line.starts_with('"') { (None, line) }
+            else { continue } // NOTE: plural forms are not used by lichen
+        };
+
+        if let Some(next) = next {
+            // a new msgctxt or msgid without a blank line still starts a new entry
+            if next != Field::Str && entry.str_.is_some() { entry.finish(&mut locale); }
+            field = Some(next);
+        }
+
+        let text = unescape(text)?;
+        match field {
+            Some(Field::Ctxt) => { entry.ctxt.get_or_insert_with(String::new).push_str(&text); },
+            Some(Field::Id) => { entry.id.get_or_insert_with(String::new).push_str(&text); },
+            Some(Field::Str) => { entry.str_.get_or_insert_with(String::new).push_str(&text); },
+            None => return Err("PO string found outside of an entry"),
+        }
+    }
+
+    entry.finish(&mut locale);
+    Ok(locale)
+}
+
+#[derive(Debug,PartialEq,Clone,Copy)]
+enum Field {
+    Ctxt,
+    Id,
+    Str,
+}
+
+#[derive(Debug,Default)]
+struct Entry {
+    ctxt: Option<String>,
+    id: Option<String>,
+    str_: Option<String>,
+    fuzzy: bool,
+}
+
+impl Entry {
+    /// Moves a finished entry into the locale, resetting for the next
+    fn finish (&mut self, locale: &mut Locale) {
+        let entry = ::std::mem::take(self);
+        if entry.fuzzy { return }
+
+        if let (Some(ctxt), Some(s)) = (entry.ctxt, entry.str_) {
+            if !s.is_empty() { locale.insert(ctxt, s); }
+        }
+    }
+}
+
+/// Lists interpolated regions and backtick symbols found in text
+///
+/// Eg: "hi {player.name}, `coins" -> ["{player.name}", "`coins"]
+pub fn placeholders (text: &str) -> Vec<String> {
+    let chars: Vec<char> = text.chars().collect();
+    let mut found = vec![];
+    let mut i = 0;
+
+    while i < chars.len() {
+        match chars[i] {
+            '{' if chars.get(i+1) == Some(&'{') => { i += 2; continue },
+            '{' => {
+                let mut depth = 0;
+                let mut region = String::new();
+                while i < chars.len() {
+                    let c = chars[i];
+                    region.push(c);
+                    i += 1;
+
+                    if c == '{' { depth += 1; }
+                    else if c == '}' {
+                        depth -= 1;
+                        if depth == 0 { break }
+                    }
+                }
+
+                found.push(region);
+                continue
+            },
+            '`' => {
+                let mut sym = "`".to_owned();
+                i += 1;
+                while i < chars.len() && chars[i] != ' ' && chars[i] != '`' {
+                    sym.push(chars[i]);
+                    i += 1;
+                }
+
+                if sym.len() > 1 { found.push(sym); }
+                continue
+            },
+            _ => {},
+        }
+
+        i += 1;
+    }
+
+    found
+}
+
+fn escape (s: &str) -> String {
+    let mut e = String::new();
+    for c in s.chars() {
+        match c {
+            '"' => e.push_str("\\\""),
+            '\\' => e.push_str("\\\\"),
+            '\n' => e.push_str("\\n"),
+            '\t' => e.push_str("\\t"),
+            _ => e.push(c),
+        }
+    }
+
+    e
+}
+
+/// Unescapes a quoted PO string, including its quotes
+fn unescape (s: &str) -> Result<String,&'static str> {
+    if s.len() < 2 || !s.starts_with('"') || !s.ends_with('"') {
+        return Err("PO string is missing its quotes")
+    }
+
+    let mut u = String::new();
+    let mut chars = s[1..s.len()-1].chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' { u.push(c); continue }
+
+        match chars.next() {
+            Some('n') => u.push('\n'),
+            Some('t') => u.push('\t'),
+            Some('"') => u.push('"'),
+            Some('\\') => u.push('\\'),
+            _ => return Err("PO string has an invalid escape"),
+        }
+    }
+
+    Ok(u)
+}
@@ -0,0 +1,103 @@
+use std::cell::RefCell;
+use std::fmt;
+
+use var::Float;
+
+/// Random source used by the evaluator, see Evaluator::set_rng
+///
+/// Implement this to plug in another generator
+pub trait Rng: Send {
+    fn next_u64 (&mut self) -> u64;
+
+    /// Copies the generator and its current state, used when saving state
+    fn box_clone (&self) -> Box<dyn Rng>;
+
+    /// Random number from 0 up to, but not including, n
+    fn below (&mut self, n: u64) -> u64 {
+        if n == 0 { return 0 }
+        self.next_u64() % n
+    }
+
+    /// Random float from 0 up to, but not including, 1
+    fn float (&mut self) -> Float {
+        ((self.next_u64() >> 11) as f64 / (1u64 << 53) as f64) as Float
+    }
+}
+
+/// Default seedable random source, xorshift64*
+///
+/// Not suitable for anything beyond games
+#[derive(Debug,PartialEq,Clone)]
+pub struct Random(u64);
+
+impl Random {
+    pub fn new (seed: u64) -> Random {
+        // NOTE: scramble the seed with splitmix64, as xorshift requires a nonzero state
+        let mut z = seed.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        Random((z ^ (z >> 31)) | 1)
+    }
+}
+
+impl Default for Random {
+    fn default () -> Random {
+        Random::new(0)
+    }
+}
+
+impl Rng for Random {
+    fn next_u64 (&mut self) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545_f491_4f6c_dd1d)
+    }
+
+    fn box_clone (&self) -> Box<dyn Rng> {
+        Box::new(self.clone())
+    }
+}
+
+/// Shared random source, usable while logic only borrows the session
+pub struct RngCell(RefCell<Box<dyn Rng>>);
+
+impl RngCell {
+    pub fn new (rng: Box<dyn Rng>) -> RngCell {
+        RngCell(RefCell::new(rng))
+    }
+
+    pub fn below (&self, n: u64) -> u64 {
+        self.0.borrow_mut().below(n)
+    }
+
+    pub fn float (&self) -> Float {
+        self.0.borrow_mut().float()
+    }
+}
+
+impl Default for RngCell {
+    fn default () -> RngCell {
+        RngCell::new(Box::new(Random::default()))
+    }
+}
+
+impl Clone for RngCell {
+    fn clone (&self) -> RngCell {
+        RngCell::new(self.0.borrow().box_clone())
+    }
+}
+
+// NOTE: we don't actually impl this, but satisfy checker
+impl PartialEq for RngCell {
+    fn eq (&self, _other: &RngCell) -> bool {
+        true
+    }
+}
+
+// NOTE: we don't actually impl this, but satisfy checker
+impl fmt::Debug for RngCell {
+    fn fmt (&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f,"RngCell")
+    }
+}
@@ -0,0 +1,160 @@
+use std::collections::HashMap;
+
+use var::{Var,Float};
+use parse::Map;
+use def::Def;
+use source::Variation;
+use rng::RngCell;
+
+/// Visit count and turn of the last visit for a node
+#[derive(Debug,PartialEq,Clone,Copy,Default)]
+pub struct Visit {
+    pub count: u64,
+    pub turn: u64,
+}
+
+/// Session state tracked by the evaluator, readable from logic
+///
+/// Saved along with the EvaluatorState
+#[derive(Debug,PartialEq,Clone,Default)]
+pub struct Session {
+    /// Turn counter, a turn passes with each choice made
+    pub turn: u64,
+    /// Visits, keyed by node name
+    pub visits: HashMap<String,Visit>,
+    /// Entries chosen per select statement, keyed by node and statement index
+    pub chosen: HashMap<(String,usize),Vec<usize>>,
+    /// Variation counters, keyed by node and statement index
+    pub variations: HashMap<(String,usize),Counter>,
+    /// Random source for shuffles, chance logic, dice and random jumps
+    pub rng: RngCell,
+}
+
+/// Times a variation was reached, and its current shuffled order
+#[derive(Debug,PartialEq,Clone,Default)]
+pub struct Counter {
+    pub count: usize,
+    pub order: Vec<usize>,
+}
+
+impl Session {
+    /// Counts a visit to a node, on the current turn
+    pub fn visit (&mut self, node: &str) {
+        let turn = self.turn;
+        let v = self.visits.entry(node.to_owned()).or_default();
+        v.count += 1;
+        v.turn = turn;
+    }
+
+    /// Times a node has been entered
+    pub fn visits (&self, node: &str) -> u64 {
+        self.visits.get(node).map_or(0, |v| v.count)
+    }
+
+    /// Turns since a node was last entered, None when never visited
+    pub fn turns_since (&self, node: &str) -> Option<u64> {
+        self.visits.get(node).map(|v| self.turn - v.turn)
+    }
+
+    /// Picks the alternative to emit for a variation statement, None when there is nothing to emit
+    pub fn vary (&mut self, node: &str, stmt: usize, kind: Variation, len: usize) -> Option<usize> {
+        if len == 0 { return None }
+
+        let rng = &self.rng;
+        let c = self.variations.entry((node.to_owned(), stmt)).or_default();
+        let n = c.count;
+        c.count += 1;
+
+        match kind {
+            Variation::Sequence => Some(n.min(len - 1)),
+            Variation::Cycle => Some(n % len),
+            Variation::Once => if n < len { Some(n) } else { None },
+            Variation::Shuffle => {
+                if n % len == 0 || c.order.len() != len {
+                    // fisher-yates
+                    c.order = (0..len).collect();
+                    for i in (1..len).rev() {
+                        let j = rng.below(i as u64 + 1) as usize;
+                        c.order.swap(i, j);
+                    }
+                }
+
+                Some(c.order[n % len])
+            },
+        }
+    }
+
+    /// Rolls dice, eg: '2d6' sums two six-sided dice, 'd20' rolls one
+    ///
+    /// Returns None when the symbol is not a dice expression
+    pub fn roll (&self, sym: &str) -> Option<Var> {
+        let (n, sides) = sym.split_at(sym.find('d')?);
+        let n: u64 = if n.is_empty() { 1 } else { n.parse().ok()? };
+        let sides: u64 = sides[1..].parse().ok()?;
+
+        // NOTE: bounded so a typo can't stall the evaluator, or overflow the total
+        if n == 0 || n > 1000 || sides == 0 || sides > 1_000_000 { return None }
+
+        let total: u64 = (0..n).map(|_| self.rng.below(sides) + 1).sum();
+        Some(Var::Int(total as i64))
+    }
+
+    /// Picks a key at random, weighted by its number, eg: {tavern 3, street 1}
+    ///
+    /// Weights may be def vars, anything not above zero is never picked
+    pub fn pick (&self, map: &Map, def: &Def) -> Option<String> {
+        let weights: Vec<(&String, Float)> = map.iter().filter_map(|(k, vals)| {
+            let w = match vals.first().map(|w| self.get_number(w, def)) {
+                Some(Ok(Var::Int(n))) => n as Float,
+                Some(Ok(Var::Num(n))) => n,
+                _ => return None,
+            };
+
+            if w > 0.0 { Some((k, w)) } else { None }
+        }).collect();
+
+        let total: Float = weights.iter().map(|w| w.1).sum();
+        let mut r = self.rng.float() * total;
+        for &(k, w) in weights.iter() {
+            if r < w { return Some(k.clone()) }
+            r -= w;
+        }
+
+        weights.last().map(|w| w.0.clone()) // NOTE: float rounding can miss the last
+    }
+
+    /// Resolves session symbols
+    ///
+    /// Eg: 'turns', 'tavern.visits', 'turns_since(tavern)'
+    /// turns_since resolves to nil for nodes never visited
+    pub fn get (&self, sym: &str) -> Option<Var> {
+        let count = |n: u64| Var::Int(n as i64);
+
+        if sym == "turns" { return Some(count(self.turn)) }
+
+        if let Some(node) = sym.strip_suffix(".visits") {
+            return Some(count(self.visits(node)))
+        }
+
+        if let Some(node) = sym.strip_prefix("turns_since(").and_then(|s| s.strip_suffix(')')) {
+            return Some(self.turns_since(node).map_or(Var::Nil, count))
+        }
+
+        None
+    }
+
+    /// Gets a number from a var, or from a session symbol missing from def
+    ///
+    /// Dice only roll here, where a number is expected, eg: 'hp - d4'
+    pub fn get_number (&self, var: &Var, def: &Def) -> Result<Var,&'static str> {
+        match Var::get_number(var, def) {
+            Err(e) => {
+                match *var {
+                    Var::Sym(ref s) => self.get(s).or_else(|| self.roll(s)).ok_or(e)?.get_number(def),
+                    _ => Err(e),
+                }
+            },
+            r => r,
+        }
+    }
+}
@@ -4,8 +4,10 @@ use logic::{Logic,LogicFn};
 use eval::{Eval,Evaluator};
 use var::{Var,Mut};
 use parse::{Parser,Map,IR};
-use def::Def;
+use def::{Def,DefBlock};
 use fun::Fun;
+use session::Session;
+use input::Input;
 
 /// Source block statement types
 #[derive(Debug,PartialEq)]
@@ -28,12 +30,32 @@ pub enum Src {
     /// Just emits variables
     Emit(Vec<Var>), 
 
+    /// Emits a line said by a speaker, the speaker symbol may reference a def var
+    ///
+    /// eg: say guard.name "Halt!" or the shorthand Guard: "Halt!"
+    Say(String, Var),
+
+    /// Emits one of the variables, picked by variation kind
+    ///
+    /// eg: emit:cycle "Morning" "Afternoon" "Evening"
+    Vary(Variation, Vec<Var>),
+
     /// Ends execution and begins next node
     Next(Next),
 
     /// Mutate type, var being mutated, argument vars
     Mut(Mut, String, Vec<Var>),
 
+    /// Command for the host to run, args resolved when reached
+    ///
+    /// eg: do play_sound "door.ogg" 0.5
+    Command(String, Vec<Var>),
+
+    /// Pauses emission for a number of host ticks, see Evaluator::tick
+    ///
+    /// eg: wait 30
+    Wait(u64),
+
     /// Match-like behavior for Mutations
     ///
     /// Map format should have Logic-Tested for the key
@@ -41,6 +63,31 @@ pub enum Src {
     When(WhenMap),
 }
 
+/// Variation kinds, picking an alternative each time the statement is reached
+#[derive(Debug,PartialEq,Clone,Copy)]
+pub enum Variation {
+    /// Steps through each, sticking on the last
+    Sequence,
+    /// Steps through each, starting over after the last
+    Cycle,
+    /// Random order, reshuffled after each pass
+    Shuffle,
+    /// Steps through each, then emits nothing
+    Once,
+}
+
+impl Variation {
+    pub fn parse (s: &str) -> Result<Variation,&'static str> {
+        match s {
+            "sequence" => Ok(Variation::Sequence),
+            "cycle" => Ok(Variation::Cycle),
+            "shuffle" => Ok(Variation::Shuffle),
+            "once" => Ok(Variation::Once),
+            _ => Err("Invalid EMIT Variation"),
+        }
+    }
+}
+
 /// Internal type to hold a specialized When-Mutate Map
 pub type WhenMap = HashMap<String,(Mut,String,Vec<Var>)>;
 
@@ -62,18 +109,49 @@ pub enum Next {
     /// Awaits for manual advancement, failure to advance continues current node
     Await(String),
 
+    /// Awaits as above, heading to the timeout's node once it runs out, see Evaluator::tick
+    ///
+    /// eg: next:await 10 inn timeout flee, the timeout heads to the awaited node unless named
+    AwaitFor(String, Timeout),
+
     /// Select from a group, based on decision
-    Select(Map),
+    Select(Select),
+
+    /// Jumps to a node picked at random, weighted, eg: next:random {tavern 3, street 1}
+    Random(Map),
+
+    /// Awaits input from the host, written to a var, eg: next:input @player.age range 1 120
+    Input(Input),
 
     /// Calls a node, pushes it onto stack
     Call(String),
 
+    /// Starts a node on a thread of its own, named after the node unless named
+    ///
+    /// eg: next:spawn companion_chat or next:spawn companion_chat companion
+    Spawn(String, String),
+
     /// Exits evaluation completely
     Exit
 }
 impl Next {
+    /// Parses an optional next trailing other vars, eg: if has_key "Unlocked" next:now vault
+    ///
+    /// A malformed next is an error, rather than being taken as vars
+    pub fn parse_tail (exp: &mut Vec<IR>) -> Result<Option<Next>,&'static str> {
+        let has_next = exp.iter().any(|ir| matches!(*ir, IR::Sym(ref s) if s.starts_with("next:")));
+        match Next::parse(exp) {
+            Err(e) if has_next => Err(e),
+            next => Ok(next.ok()),
+        }
+    }
+
     pub fn parse(exp: &mut Vec<IR>) -> Result<Next,&'static str> {
         let mut select_idx = None;
+        let mut random_idx = None;
+        let mut input_idx = None;
+        let mut spawn_idx = None;
+        let mut await_idx = None;
         for (i,n) in exp.iter().enumerate() {
             match n {
                 &IR::Sym(ref s) => {
@@ -81,18 +159,115 @@ impl Next {
                         select_idx = Some(i);
                         break
                     }
+                    else if s == "next:random" {
+                        random_idx = Some(i);
+                        break
+                    }
+                    else if s == "next:input" {
+                        input_idx = Some(i);
+                        break
+                    }
+                    else if s == "next:spawn" {
+                        spawn_idx = Some(i);
+                        break
+                    }
+                    else if s == "next:await" {
+                        await_idx = Some(i);
+                        break
+                    }
                 },
                 _ => {},
             }
         }
         
+        // random jumps also take a map, of nodes and their weights
+        if let Some(idx) = random_idx {
+            if idx + 1 >= exp.len() { return Err("Cannot parse map") }
+            let map_ir = exp.remove(idx+1);
+            let _ = exp.remove(idx); // next:random statement
+            let map = Parser::parse_map(map_ir).map_err(|_| "Cannot parse map")?;
+            if map.iter().any(|(_,vals)| vals.len() != 1) {
+                return Err("Random weights must be a single number")
+            }
+
+            return Ok(Next::Random(map))
+        }
+
+        // spawns take a node, and optionally the thread's name
+        if let Some(idx) = spawn_idx {
+            let mut args = exp.split_off(idx + 1).into_iter().map(|ir| match ir {
+                IR::Sym(s) => Ok(s),
+                _ => Err("Invalid Spawn node"),
+            });
+            let _ = exp.pop(); // next:spawn statement
+
+            let node = args.next().ok_or("Spawn is missing its node")??;
+            let name = args.next().unwrap_or_else(|| Ok(node.clone()))?;
+            if args.next().is_some() { return Err("Invalid Spawn syntax") }
+
+            return Ok(Next::Spawn(node, name))
+        }
+
+        // timed awaits lead with their ticks, eg: next:await 10 inn timeout flee
+        if let Some(idx) = await_idx.filter(|&idx| exp.len() > idx + 2) {
+            let mut args = exp.split_off(idx + 1).into_iter();
+            let _ = exp.pop(); // next:await statement
+
+            let ticks = Timeout::ticks(args.next())?.ok_or("Invalid Await timeout")?;
+            let node: String = args.next().ok_or("Await is missing its node")?.into();
+            let target = match (args.next(), args.next()) {
+                (None, _) => node.clone(),
+                (Some(IR::Sym(ref t)), Some(target)) if t == "timeout" => target.into(),
+                _ => return Err("Invalid Await syntax"),
+            };
+            if args.next().is_some() { return Err("Invalid Await syntax") }
+
+            return Ok(Next::AwaitFor(node, Timeout { ticks, target }))
+        }
+
+        // input takes its target and every check after it
+        if let Some(idx) = input_idx {
+            let input = exp.split_off(idx + 1);
+            let _ = exp.pop(); // next:input statement
+            return Ok(Next::Input(Input::parse(input)?))
+        }
         
         // handle nested selects as a special case
         if let Some(idx) = select_idx {
+            if idx + 1 >= exp.len() { return Err("Cannot parse map") }
+            let ticks = match Timeout::ticks(exp.get(idx+1).cloned())? {
+                Some(ticks) => { let _ = exp.remove(idx+1); Some(ticks) },
+                None => None,
+            };
+            if idx + 1 >= exp.len() { return Err("Cannot parse map") }
+
             let map_ir = exp.remove(idx+1);
             let _ = exp.remove(idx); // next:select statement
             if let Ok(map) = Parser::parse_map(map_ir) {
-                return Ok(Next::Select(map))
+                for (_,vals) in map.iter() { Choice::entry(vals)?; }
+
+                // optional fallback for when every choice is used up,
+                // and the timeout's node, in either order
+                let mut select: Select = map.into();
+                let mut target = None;
+                while let Some(IR::Sym(opt)) = exp.get(idx).cloned() {
+                    let _ = exp.remove(idx);
+                    if idx >= exp.len() { return Err("Select option is missing its node") }
+                    match &opt[..] {
+                        "fallback" if select.fallback.is_none() => { select.fallback = Some(exp.remove(idx).into()); },
+                        "timeout" if target.is_none() => { target = Some(exp.remove(idx).into()); },
+                        _ => return Err("Invalid Select option"),
+                    }
+                }
+
+                select.timeout = match (ticks, target) {
+                    (Some(ticks), Some(target)) => Some(Timeout { ticks, target }),
+                    (None, None) => None,
+                    (Some(_), None) => return Err("Select timeout is missing its node"),
+                    (None, Some(_)) => return Err("Select timeout is missing its ticks"),
+                };
+                
+                return Ok(Next::Select(select))
             }
             else { return Err("Cannot parse map") }
         }
@@ -160,12 +335,126 @@ impl Next {
     }
 }
 
+/// Select entries, with an optional fallback node
+#[derive(Debug,PartialEq,Clone,Default)]
+pub struct Select {
+    pub map: Map,
+    /// Advanced to once every once-only entry has been chosen
+    pub fallback: Option<String>,
+    /// Advanced to when no choice is made in time
+    pub timeout: Option<Timeout>,
+}
+
+impl From<Map> for Select {
+    fn from (map: Map) -> Select {
+        Select { map, fallback: None, timeout: None }
+    }
+}
+
+/// Number of host ticks to wait, and the node to head to once they've passed
+#[derive(Debug,PartialEq,Clone)]
+pub struct Timeout {
+    pub ticks: u64,
+    pub target: String,
+}
+
+impl Timeout {
+    /// Reads a number of ticks, None when the token isn't a number
+    fn ticks (ir: Option<IR>) -> Result<Option<u64>,&'static str> {
+        match ir {
+            Some(IR::Sym(ref s)) if s.starts_with(|c: char| c.is_ascii_digit()) => {
+                s.parse().map(Some).map_err(|_| "Invalid timeout ticks")
+            },
+            _ => Ok(None),
+        }
+    }
+}
+
+/// A single select entry, indexed by its position in source
+#[derive(Debug,PartialEq,Clone)]
+pub struct Choice {
+    /// Text to present
+    pub text: String,
+    /// Node to advance to
+    pub target: String,
+    /// False when the entry's guard failed
+    pub available: bool,
+    /// Why the choice is unavailable, the guard's else text or its name
+    pub reason: Option<String>,
+    /// Once-only choices are removed after being chosen, others are sticky
+    pub once: bool,
+}
+
+/// Options following a select entry's target
+#[derive(Debug,Default)]
+struct Entry<'a> {
+    once: bool,
+    guard: Option<&'a Var>,
+    reason: Option<&'a Var>,
+}
+
+impl Choice {
+    /// Lists select entries as choices, in source order
+    ///
+    /// Entries may be marked once or sticky (the default), and guarded,
+    /// eg: "Bribe" bribe once if has_gold else "Not enough gold"
+    /// Guards resolve as logic or def vars, anything but true marks the choice unavailable
+    pub fn list (map: &Map,
+                 logic: &HashMap<String,LogicFn>,
+                 def: &Def,
+                 session: &Session) -> Vec<Choice> {
+        map.iter().map(|(text, vals)| {
+            let entry = Choice::entry(vals).unwrap_or_default(); // NOTE: checked during parse
+            let mut choice = Choice {
+                text: text.clone(),
+                target: vals.first().map_or_else(String::new, |v| v.to_string()),
+                available: true,
+                reason: None,
+                once: entry.once,
+            };
+
+            if let Some(guard) = entry.guard {
+                let guard = guard.to_string();
+                if Evaluator::resolve(&guard, logic, def, session) != Some(Var::Bool(true)) {
+                    choice.available = false;
+                    choice.reason = Some(entry.reason.map_or(guard, |r| r.to_string()));
+                }
+            }
+
+            choice
+        }).collect()
+    }
+
+    /// Parses the options of a select entry, eg: 'if' without a guard is invalid
+    fn entry (vals: &[Var]) -> Result<Entry<'_>,&'static str> {
+        let mut entry = Entry::default();
+        let mut opts = vals.iter().skip(1);
+        while let Some(opt) = opts.next() {
+            match opt.to_string().as_str() {
+                "once" => entry.once = true,
+                "sticky" => entry.once = false,
+                "if" if entry.guard.is_none() => {
+                    entry.guard = Some(opts.next().ok_or("Invalid select guard")?);
+                },
+                "else" if entry.guard.is_some() && entry.reason.is_none() => {
+                    entry.reason = Some(opts.next().ok_or("Invalid select guard")?);
+                },
+                "if" | "else" => return Err("Invalid select guard"),
+                _ => {}, // entries can carry extra vars
+            }
+        }
+
+        Ok(entry)
+    }
+}
+
 
 impl Src {
     pub fn eval (&self,
                  logic: &mut HashMap<String,LogicFn>,
                  def: &mut Def,
-                 fun: &mut HashMap<String,Fun>)
+                 fun: &mut HashMap<String,Fun>,
+                 session: &Session)
                  -> (Vec<Var>,Option<Next>)
     {
         match self {
@@ -175,49 +464,57 @@ impl Src {
                         let mut num = None;
 
                         let var_name = Var::Sym(v.to_owned());
-                        let v1 = Var::get_num(&var_name, def);
+                        let v1 = var_name.get_number(def);
                         
                         if let Ok(v1) = v1 {
                             let var_name = &a[0];
-                            let v2 = Var::get_num(&var_name, def);
+                            let v2 = session.get_number(var_name, def);
                             
                             if let Ok(v2) = v2 {
-                                match m {
-                                    &Mut::Add => {
-                                        num = Some(v1+v2);
-                                    },
-                                    &Mut::Sub => {
-                                        num = Some(v1-v2);
-                                    },
-                                    &Mut::Mul => {
-                                        num = Some(v1*v2);
-                                    },
-                                    &Mut::Div => {
-                                        num = Some(v1/v2);
-                                    },
-                                    _ => {},
-                                }
+                                num = m.apply_num(&v1, &v2);
                             }
                         }
                         
                         if let Some(num) = num {
-                            def.set_path(&v, Var::Num(num));
+                            def.set_path(v, num);
                         }
                     },
                     &Mut::Swap => {
-                        let val = a[0].clone();
+                        // dice are rolled, eg: @damage 2d6
+                        let val = match a[0] {
+                            Var::Sym(ref sym) => session.roll(sym),
+                            _ => None,
+                        }.unwrap_or_else(|| a[0].clone());
                         def.set_path(v,val); // NOTE: this will also build a var from scratch
                     },
+                    &Mut::Unset => {
+                        let _ = def.unset_path(v);
+                    },
+                    &Mut::Delete => {
+                        let _ = def.delete(v);
+                    },
                     &Mut::New => {
                         match a[0] {
                             Var::Sym(ref sym) => {
-                                let mut block = None;
+                                // templates are either whole blocks or nested maps
+                                let mut data = None;
                                 if let Some(b) = def.get(sym) {
-                                    block = Some(b.clone());
+                                    data = Some(b.data.clone());
+                                }
+                                else if let Some(Var::Map(m)) = def.get_path(sym) {
+                                    data = Some(m);
                                 }
 
-                                if let Some(block) = block {
-                                    def.insert(v.to_string(), block);
+                                // NOTE: cloning the map deep-copies any nested maps too
+                                if let Some(data) = data {
+                                    if v.contains('.') {
+                                        def.set_path(v, Var::Map(data));
+                                    }
+                                    else {
+                                        let mut block = DefBlock::new(v);
+                                        block.data = data;
+                                        def.insert(v.to_string(), block);
+                                    }
                                 }
                             },
                             _ => { } // We do nothing with other var types
@@ -229,7 +526,7 @@ impl Src {
                         for n in a {
                             match n {
                                 &Var::Sym(ref n) => {
-                                    if let Some(v) = Evaluator::resolve(n, &logic, &def) {
+                                    if let Some(v) = Evaluator::resolve(n, &logic, &def, session) {
                                         args.push(v)
                                     }
                                 },
@@ -256,6 +553,21 @@ impl Src {
             &Src::Emit(ref vars) => {
                 return (vars.clone(),None)
             },
+            Src::Say(speaker, text) => {
+                (vec![Var::Sym(speaker.clone()), text.clone()], None)
+            },
+            &Src::Vary(_,_) => {
+                // NOTE: picked by the evaluator, which keeps the counters
+                (vec![],None)
+            },
+            &Src::Command(_,_) => {
+                // NOTE: resolved by the evaluator, as a command event
+                (vec![],None)
+            },
+            &Src::Wait(_) => {
+                // NOTE: the evaluator keeps the time
+                (vec![],None)
+            },
             &Src::Logic(ref name, ref logic_src)=> {
                 // NOTE: we only add logicfn if not compiled yet!
                 if !logic.contains_key(name) {
@@ -268,9 +580,10 @@ impl Src {
             &Src::If(ref lookup, ref v, ref next) => {
                 let mut is_true = false;
                 
-                if let Some(val) = Evaluator::resolve(lookup, logic, def) {
+                if let Some(val) = Evaluator::resolve(lookup, logic, def, session) {
                     match val {
                         Var::Bool(v) => { is_true = v; },
+                        Var::Nil => {},
                         _ => { is_true = lookup != &val.to_string(); }
                     }
                 }
@@ -281,9 +594,10 @@ impl Src {
             &Src::When(ref map) => {
                 for (k, &(ref m, ref v, ref a)) in map.iter() {
                     let mut is_true = false;
-                    if let Some(val) = Evaluator::resolve(k, logic, def) {
+                    if let Some(val) = Evaluator::resolve(k, logic, def, session) {
                         match val {
                             Var::Bool(v) => { is_true = v; },
+                            Var::Nil => {},
                             _ => { is_true = k != &val.to_string(); }
                         }
                     }
@@ -292,7 +606,8 @@ impl Src {
                         Src::eval(&Src::Mut(m.clone(), v.clone(), a.clone()),
                                   logic,
                                   def,
-                                  fun);
+                                  fun,
+                                  session);
                     }
                 }
                 
@@ -312,9 +627,9 @@ impl Src {
                 }
                 else if sym == "when" {
                     if exp.len() != 1 { return Err("Invalid WHEN Logic") }
-                    if let Ok(mut map) = Parser::parse_map(exp.pop().unwrap()) {
+                    if let Ok(map) = Parser::parse_map(exp.pop().unwrap()) {
                         let mut when_map: WhenMap = HashMap::new();
-                        for (k,mut v) in map.drain() {
+                        for (k,mut v) in map {
                             let v_ir = v.drain(..).map(|n| n.into()).collect();
                             let m = try!(Src::parse(v_ir));
                             match m {
@@ -334,7 +649,7 @@ impl Src {
                     if exp.len() < 2 { return Err("Invalid IF Logic") }
 
                     let x = exp.remove(0);
-                    let next = Next::parse(&mut exp);
+                    let next = Next::parse_tail(&mut exp)?;
                     
                     let mut v = vec![];
                     for n in exp.drain(..) {
@@ -343,12 +658,12 @@ impl Src {
                     }
 
                     Ok(Src::If(x.into(), // NOTE: x.into() might cause errors, not all IR is acceptable
-                               v, next.ok()))
+                               v, next))
                 }
                 else if sym == "or" {
                     if exp.len() < 1 { return Err("Invalid OR Logic") }
 
-                    let next = Next::parse(&mut exp);
+                    let next = Next::parse_tail(&mut exp)?;
                     
                     let mut v = vec![];
                     for n in exp.drain(..) {
@@ -356,7 +671,7 @@ impl Src {
                         v.push(r);
                     }
                     
-                    Ok(Src::Or(v,next.ok()))
+                    Ok(Src::Or(v,next))
                 }
                 else if &sym.split_terminator(':').next() == &Some("next") {
                     exp.insert(0, IR::Sym(sym.to_owned()));
@@ -378,6 +693,51 @@ impl Src {
                     }
                     else { Err("Missing EMIT Logic") }
                 }
+                else if sym == "say" {
+                    if exp.len() != 2 { return Err("Invalid SAY Logic") }
+                    let text = Var::parse(exp.pop().unwrap())?;
+                    match exp.pop() {
+                        Some(IR::Sym(speaker)) => Ok(Src::Say(speaker, text)),
+                        _ => Err("Invalid SAY speaker"),
+                    }
+                }
+                else if sym.len() > 1 && sym.ends_with(':') && exp.len() == 1 { // speaker shorthand
+                    let text = Var::parse(exp.pop().unwrap())?;
+                    Ok(Src::Say(sym[..sym.len()-1].to_owned(), text))
+                }
+                else if sym == "do" {
+                    if exp.is_empty() { return Err("Missing DO Command") }
+                    let name = match exp.remove(0) {
+                        IR::Sym(name) => name,
+                        _ => return Err("Invalid DO Command name"),
+                    };
+
+                    let mut args = vec![];
+                    for e in exp.drain(..) {
+                        args.push(Var::parse(e)?);
+                    }
+
+                    Ok(Src::Command(name, args))
+                }
+                else if sym == "wait" {
+                    let ticks = match exp.pop() {
+                        Some(IR::Sym(ref n)) if exp.is_empty() => n.parse().map_err(|_| "Invalid WAIT ticks")?,
+                        _ => return Err("Invalid WAIT Logic"),
+                    };
+
+                    Ok(Src::Wait(ticks))
+                }
+                else if let Some(kind) = sym.strip_prefix("emit:") {
+                    let kind = Variation::parse(kind)?;
+                    if exp.is_empty() { return Err("Missing EMIT Logic") }
+
+                    let mut v = vec![];
+                    for e in exp.drain(..) {
+                        v.push(Var::parse(e)?);
+                    }
+
+                    Ok(Src::Vary(kind, v))
+                }
                 else {
                     let mut keys: Vec<&str> = sym.split_terminator(':').collect();
                     if keys.len() < 2 { // regular logic
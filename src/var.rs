@@ -1,25 +1,80 @@
+use std::collections::HashMap;
+use std::cmp::Ordering;
+
 use eval::Eval;
 use parse::IR;
 use def::Def;
 
+/// Float type backing Num, enable the 'f64' feature for double precision
+#[cfg(not(feature = "f64"))]
+pub type Float = f32;
+#[cfg(feature = "f64")]
+pub type Float = f64;
+
 /// Supported Var Types
 ///
 /// These are parsed from IR variants
-#[derive(Debug,Clone, PartialEq)]
+#[derive(Debug,Clone)]
 pub enum Var {
     String(String),
-    Num(f32),
+    Num(Float),
+    /// Whole numbers, parsed from literals without a decimal point
+    Int(i64),
     Bool(bool),
     Sym(String),
+
+    /// Nested key-value data, eg: 'party.members' holding its own fields
+    Map(HashMap<String,Var>),
+
+    /// An unset value, parsed from 'nil'
+    Nil,
 }
 
 impl ToString for Var {
     fn to_string(&self) -> String {
-        match self {
-            &Var::String(ref s) => s.clone(),
-            &Var::Sym(ref s) => s.clone(),
-            &Var::Num(ref n) => n.to_string(),
-            &Var::Bool(ref b) => b.to_string(),
+        match *self {
+            Var::String(ref s) => s.clone(),
+            Var::Sym(ref s) => s.clone(),
+            Var::Num(ref n) => n.to_string(),
+            Var::Int(ref n) => n.to_string(),
+            Var::Bool(ref b) => b.to_string(),
+            Var::Nil => "nil".to_owned(),
+            Var::Map(ref m) => {
+                // NOTE: keys are sorted so the output is stable between runs
+                let mut keys: Vec<&String> = m.keys().collect();
+                keys.sort();
+
+                let mut s = "{".to_owned();
+                for (i,k) in keys.iter().enumerate() {
+                    if i > 0 { s.push_str(", "); }
+                    s.push_str(k);
+                    s.push(' ');
+                    s.push_str(&m[*k].to_string());
+                }
+                s.push('}');
+
+                s
+            },
+        }
+    }
+}
+
+/// Ints and Nums compare by value, so 6 and 6.0 are equal
+impl PartialEq for Var {
+    fn eq(&self, other: &Var) -> bool {
+        match (self, other) {
+            (Var::String(a), Var::String(b)) => a == b,
+            (Var::Sym(a), Var::Sym(b)) => a == b,
+            (Var::Bool(a), Var::Bool(b)) => a == b,
+            (Var::Map(a), Var::Map(b)) => a == b,
+            (Var::Num(a), Var::Num(b)) => a == b,
+            (Var::Int(a), Var::Int(b)) => a == b,
+            (Var::Nil, Var::Nil) => true,
+            (Var::Int(a), Var::Num(b)) |
+            (Var::Num(b), Var::Int(a)) => {
+                b.fract() == 0. && *b as i64 == *a && *a as Float == *b
+            },
+            _ => false,
         }
     }
 }
@@ -29,11 +84,16 @@ impl From<bool> for Var {
         Var::Bool(t)
     }
 }
-impl From<f32> for Var {
-    fn from(t:f32) -> Var {
+impl From<Float> for Var {
+    fn from(t:Float) -> Var {
         Var::Num(t)
     }
 }
+impl From<i64> for Var {
+    fn from(t:i64) -> Var {
+        Var::Int(t)
+    }
+}
 impl From<String> for Var {
     fn from(t:String) -> Var {
         Var::String(t)
@@ -49,12 +109,16 @@ impl Var {
     pub fn parse(t: IR) -> Result<Var,&'static str> {
         match t {
             IR::Sym(t) => {
-                if let Ok(v) = t.parse::<f32>() {
+                if let Ok(v) = t.parse::<i64>() {
+                    Ok(Var::Int(v))
+                }
+                else if let Ok(v) = t.parse::<Float>() {
                     Ok(Var::Num(v))
                 }
                 else if let Ok(v) = t.parse::<bool>() {
                     Ok(Var::Bool(v))
                 }
+                else if t == "nil" { Ok(Var::Nil) }
                 else { Ok(Var::Sym(t)) }
             },
             IR::String(s) => { Ok(Var::String(s)) },
@@ -63,14 +127,25 @@ impl Var {
     }
 
     /// Get any underlying number
-    pub fn get_num (&self, data: &Def) -> Result<f32,&'static str> {
+    ///
+    /// Ints are converted to a Float, see get_number to keep the original type
+    pub fn get_num (&self, data: &Def) -> Result<Float,&'static str> {
+        match self.get_number(data)? {
+            Var::Int(n) => Ok(n as Float),
+            Var::Num(n) => Ok(n),
+            _ => Err("ERROR: NaN Evaluation")
+        }
+    }
+
+    /// Get any underlying number as either an Int or Num var
+    pub fn get_number (&self, data: &Def) -> Result<Var,&'static str> {
         let num;
         match self {
-            &Var::Num(n) => { num = n; },
+            &Var::Num(_) | &Var::Int(_) => { num = self.clone(); },
             &Var::Sym(ref s) => {
                 if let Some((n,_res)) = data.get_last(s) {
                     match n {
-                        Var::Num(n) => { num = n; },
+                        Var::Num(_) | Var::Int(_) => { num = n; },
                         _ => return Err("ERROR: NaN Evaluation")
                     }
                 }
@@ -81,6 +156,17 @@ impl Var {
 
         return Ok(num)
     }
+
+    /// Compares two numeric vars, Ints are compared exactly against each other
+    pub fn cmp_num (&self, other: &Var) -> Option<Ordering> {
+        match (self, other) {
+            (&Var::Int(a), &Var::Int(b)) => Some(a.cmp(&b)),
+            (&Var::Int(a), &Var::Num(b)) => (a as Float).partial_cmp(&b),
+            (&Var::Num(a), &Var::Int(b)) => a.partial_cmp(&(b as Float)),
+            (&Var::Num(a), &Var::Num(b)) => a.partial_cmp(&b),
+            _ => None,
+        }
+    }
 }
 
 /// Mutable state functions
@@ -95,12 +181,55 @@ pub enum Mut {
     /// Swaps value
     Swap,
 
+    /// Removes a field, eg: @player.name unset
+    Unset,
+
+    /// Removes a whole def block, eg: @player delete
+    Delete,
+
     /// Custom function reference
     Fn(String),
 }
 
 
 impl Mut {
+    /// Applies an arithmetic mutation to two numeric vars
+    ///
+    /// Ints stay Ints unless mixed with a Num, which promotes the result to a Num.
+    /// Int math saturates on overflow and Int division truncates towards zero.
+    /// Dividing by zero returns None, leaving the mutated var untouched.
+    pub fn apply_num (&self, left: &Var, right: &Var) -> Option<Var> {
+        match (left, right) {
+            (&Var::Int(a), &Var::Int(b)) => {
+                match *self {
+                    Mut::Add => Some(Var::Int(a.saturating_add(b))),
+                    Mut::Sub => Some(Var::Int(a.saturating_sub(b))),
+                    Mut::Mul => Some(Var::Int(a.saturating_mul(b))),
+                    Mut::Div => {
+                        if b == 0 { None }
+                        else { Some(Var::Int(a.checked_div(b).unwrap_or(i64::MAX))) }
+                    },
+                    _ => None,
+                }
+            },
+            _ => {
+                let a = match *left { Var::Int(n) => n as Float, Var::Num(n) => n, _ => return None };
+                let b = match *right { Var::Int(n) => n as Float, Var::Num(n) => n, _ => return None };
+
+                match *self {
+                    Mut::Add => Some(Var::Num(a+b)),
+                    Mut::Sub => Some(Var::Num(a-b)),
+                    Mut::Mul => Some(Var::Num(a*b)),
+                    Mut::Div => {
+                        if b == 0. { None }
+                        else { Some(Var::Num(a/b)) }
+                    },
+                    _ => None,
+                }
+            },
+        }
+    }
+
     /// Parses in a custom function, symbol must be surrounded by (parenthesis)
     pub fn parse_fn (mut exp: String) -> Option<String> {
         if exp.chars().next() == Some('(') {
@@ -155,9 +284,16 @@ impl Mut {
         }
         else {
             let r = try!(Var::parse(exps.pop().unwrap()));
-            a.push(r);
             v = exps.pop().unwrap().into();
-            m = Mut::Swap;
+
+            match r {
+                Var::Sym(ref s) if s == "unset" => { m = Mut::Unset },
+                Var::Sym(ref s) if s == "delete" => { m = Mut::Delete },
+                _ => {
+                    a.push(r.clone());
+                    m = Mut::Swap;
+                },
+            }
         }
 
         let _ = v.remove(0); // remove @ in var name
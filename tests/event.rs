@@ -0,0 +1,798 @@
+extern crate lichen;
+
+use lichen::parse::Parser;
+use lichen::eval::{Evaluator,Schedule};
+use lichen::event::Event;
+use lichen::source::{Choice,Next};
+use lichen::var::Var;
+use lichen::command::{Arity,Command};
+
+use std::collections::HashMap;
+
+#[test]
+fn typed_events() {
+    let src = "root\n
+    emit \"Hello\"\n
+    next:call store\n
+    next:await exit_node\n
+;\n
+store\n
+    emit \"Welcome\" \"Traveller\"\n
+;\n
+exit_node\n
+    next:select {\"Leave\" leave}\n
+;";
+
+    let mut env = Parser::parse_blocks(src).expect("ERROR: Unable to parse source").into_env();
+    let mut ev = Evaluator::new(&mut env);
+
+    assert_eq!(ev.next_event(), Some(Event::NodeEntered { node: "root".to_owned(), idx: 0, tags: vec![] }));
+    match ev.next_event() {
+        Some(Event::Line { node, idx, vars, ids, .. }) => {
+            assert_eq!((&node[..], idx), ("root", 0));
+            assert_eq!(vars, vec!["Hello".into()]);
+            assert_eq!(ids.len(), 1);
+        },
+        e => panic!("ERROR: Expected line, got {:?}", e),
+    }
+
+    assert_eq!(ev.next_event(), Some(Event::NodeEntered { node: "store".to_owned(), idx: 0, tags: vec![] }));
+    let e = ev.next_event().unwrap();
+    assert_eq!((e.node(), e.idx()), ("store", 0));
+    assert_eq!(ev.next_event(), Some(Event::NodeExited { node: "store".to_owned(), idx: 1 }));
+
+    assert_eq!(ev.next_event(), Some(Event::AwaitAdvance { node: "root".to_owned(),
+                                                          idx: 2,
+                                                          target: "exit_node".to_owned() }));
+    ev.advance("exit_node".to_owned());
+    assert_eq!(ev.next_event(), Some(Event::NodeExited { node: "root".to_owned(), idx: 3 }));
+    assert_eq!(ev.next_event(), Some(Event::NodeEntered { node: "exit_node".to_owned(), idx: 0, tags: vec![] }));
+    match ev.next_event() {
+        Some(Event::Choices { node, choices, .. }) => {
+            assert_eq!(node, "exit_node");
+            assert_eq!(choices[0].text, "Leave");
+        },
+        e => panic!("ERROR: Expected choices, got {:?}", e),
+    }
+
+    // choices not taken continue the node, which then ends
+    let events: Vec<Event> = ev.events().collect();
+    assert_eq!(events, vec![Event::NodeExited { node: "exit_node".to_owned(), idx: 1 },
+                            Event::Finished { node: "exit_node".to_owned(), idx: 0 }]);
+    assert_eq!(ev.next_event(), None);
+}
+
+#[test]
+fn tuple_compat() {
+    let src = "root\n
+    emit \"Hello\"\n
+    next:now other\n
+;\n
+other\n
+    emit \"Bye\"\n
+;";
+
+    let mut env = Parser::parse_blocks(src).expect("ERROR: Unable to parse source").into_env();
+    let mut ev = Evaluator::new(&mut env);
+
+    let steps: Vec<_> = ev.by_ref().map(|(vars,_)| vars).collect();
+    assert_eq!(steps, vec![vec!["Hello".into()], vec![], vec!["Bye".into()]]);
+    assert_eq!(ev.events().last(), Some(Event::Finished { node: "other".to_owned(), idx: 0 }));
+}
+
+#[test]
+fn ordered_choices() {
+    let src = "root\n
+    next:select {\"Zebra\" zoo, \"Apple\" orchard, \"Mango\" orchard, \"Kiwi\" zoo}\n
+;\n
+zoo\n
+    emit \"At the zoo\"\n
+;";
+
+    let mut env = Parser::parse_blocks(src).expect("ERROR: Unable to parse source").into_env();
+    let mut ev = Evaluator::new(&mut env);
+
+    assert!(ev.choose(0).is_err()); // nothing pending yet
+    let _ = ev.next().unwrap();
+
+    let texts: Vec<&str> = ev.choices().iter().map(|c| &c.text[..]).collect();
+    assert_eq!(texts, vec!["Zebra", "Apple", "Mango", "Kiwi"]);
+    assert_eq!(ev.choices()[1], Choice { text: "Apple".to_owned(),
+                                         target: "orchard".to_owned(),
+                                         available: true,
+                                         reason: None,
+                                         once: false });
+
+    assert!(ev.choose(4).is_err());
+    assert!(ev.choose(3).is_ok());
+    assert!(ev.choices().is_empty());
+
+    let (vars,_) = ev.next().unwrap();
+    assert_eq!(vars, vec!["At the zoo".into()]);
+}
+
+#[test]
+fn guarded_choices() {
+    let src = "root\n
+    has_gold player.gold\n
+    next:select {\"Bribe\" bribe if has_gold else \"Not enough gold\", \"Threaten\" fight if player.brave, \"Leave\" exit}\n
+;\n
+def player\n
+    gold false\n
+    brave true\n
+;";
+
+    let mut env = Parser::parse_blocks(src).expect("ERROR: Unable to parse source").into_env();
+    let mut ev = Evaluator::new(&mut env);
+    let _ = ev.next().unwrap();
+
+    let choices = ev.choices().to_vec();
+    assert_eq!(choices.len(), 3);
+    assert!(!choices[0].available);
+    assert_eq!(choices[0].reason, Some("Not enough gold".to_owned()));
+    assert_eq!(choices[0].target, "bribe");
+    assert!(choices[1].available);
+    assert!(choices[2].available);
+
+    assert_eq!(ev.choose(0), Err("Choice is unavailable"));
+    assert!(ev.choose(1).is_ok());
+
+    // every choice guarded out is still listed, greyed out
+    let src = "root\n    next:select {\"Bribe\" bribe if player.gold, \"Threaten\" fight if player.gold}\n;\ndef player\n    gold false\n;";
+    let mut env = Parser::parse_blocks(src).expect("ERROR: Unable to parse source").into_env();
+    let mut ev = Evaluator::new(&mut env);
+    let events: Vec<Event> = ev.events().collect();
+    assert!(events.iter().any(|e| matches!(*e, Event::Choices { ref choices, .. } if choices.len() == 2 && !choices[0].available && !choices[1].available)));
+
+    assert!(Parser::parse_blocks("root\n    next:select {\"Bribe\" bribe if, \"Leave\" exit}\n;").is_err());
+}
+
+#[test]
+fn once_only_choices() {
+    let src = "hub\n
+    next:select {\"Ask about the war\" war once, \"Ask about the king\" king once, \"Wait\" hub sticky if waiting} fallback done\n
+;\n
+war\n
+    next:now hub\n
+;\n
+king\n
+    next:now hub\n
+;\n
+done\n
+    emit \"Nothing left to ask\"\n
+;\n
+def root\n
+    waiting false\n
+;";
+
+    let mut env = Parser::parse_blocks(src).expect("ERROR: Unable to parse source").into_env();
+    let mut ev = Evaluator::new(&mut env);
+    ev.advance("hub".to_owned());
+
+    let _ = ev.next().unwrap();
+    assert_eq!(ev.choices().len(), 3);
+    assert!(ev.choices()[0].once && !ev.choices()[2].once);
+    assert!(ev.choose(0).is_ok());
+
+    let _ = ev.next().unwrap(); // war heads back to hub
+    let _ = ev.next().unwrap();
+    let texts: Vec<&str> = ev.choices().iter().map(|c| &c.text[..]).collect();
+    assert_eq!(texts, vec!["Ask about the king", "Wait"]);
+    assert!(ev.choose(0).is_ok());
+
+    // saved state keeps track of chosen entries
+    let state = ev.save();
+    let mut ev = state.to_eval(&mut env);
+    let _ = ev.next().unwrap();
+
+    // only the unavailable sticky choice is left, so the select falls through
+    let (_,next) = ev.next().unwrap();
+    assert_eq!(next, Some(Next::Now("done".to_owned())));
+    assert!(ev.choices().is_empty());
+    let (vars,_) = ev.next().unwrap();
+    assert_eq!(vars, vec!["Nothing left to ask".into()]);
+
+    assert!(Parser::parse_blocks("root\n    next:select {\"Ask\" ask} fallback\n;").is_err());
+}
+
+#[test]
+fn input_prompts() {
+    let src = "root\n
+    emit \"How old are you?\"\n
+    next:input @player.age range 1 120\n
+    emit \"And your name?\"\n
+    next:input @player.name max 12 pattern \"[A-Z][a-z]* ?[A-Za-z]*\"\n
+    emit \"Welcome, {player.name} ({player.age})\"\n
+;\n
+def player\n
+    age 0\n
+;";
+
+    let mut env = Parser::parse_blocks(src).expect("ERROR: Unable to parse source").into_env();
+    let mut ev = Evaluator::new(&mut env);
+
+    assert_eq!(ev.submit_input("Ann".into()), Err("No input is pending"));
+    let _ = ev.next_event(); // entered
+    let _ = ev.next_event(); // line
+    let input = match ev.next_event() {
+        Some(Event::Input { input, error, idx, .. }) => {
+            assert_eq!((idx, error), (1, None));
+            input
+        },
+        e => panic!("ERROR: Expected input, got {:?}", e),
+    };
+    assert_eq!(input.target, "player.age");
+    assert_eq!(ev.input(), Some(&input));
+
+    // refused input asks again, along with why
+    assert_eq!(ev.submit_input("old".into()), Err("Input must be a number"));
+    assert_eq!(ev.submit_input(Var::Int(130)), Err("Input is out of range"));
+    assert_eq!(ev.next_event(), Some(Event::Input { node: "root".to_owned(),
+                                                    idx: 1,
+                                                    input: input.clone(),
+                                                    error: Some("Input is out of range".to_owned()) }));
+
+    assert!(ev.submit_input(" 42 ".into()).is_ok());
+    assert!(ev.input().is_none());
+
+    let _ = ev.next_event(); // line
+    let _ = ev.next_event(); // name input
+    assert_eq!(ev.submit_input("Ann Lee the Third".into()), Err("Input is too long"));
+    assert_eq!(ev.submit_input("ann".into()), Err("Input does not match the pattern"));
+    assert!(ev.submit_input("Ann Lee".into()).is_ok());
+
+    match ev.next_event() {
+        Some(Event::Line { vars, .. }) => assert_eq!(vars, vec!["Welcome, Ann Lee (42)".into()]),
+        e => panic!("ERROR: Expected line, got {:?}", e),
+    }
+    assert_eq!(ev.session().visits("root"), 1);
+
+    // nothing steps while input is asked for, so iterating ends
+    let src = "root\n    emit \"Name?\"\n    next:input @player.name\n    emit \"Hi\"\n;";
+    let mut env = Parser::parse_blocks(src).expect("ERROR: Unable to parse source").into_env();
+    let mut ev = Evaluator::new(&mut env);
+    let steps: Vec<_> = ev.by_ref().collect();
+    assert_eq!(steps.len(), 2);
+    assert!(ev.input().is_some());
+    assert!(ev.next().is_none());
+
+    let mut env = Parser::parse_blocks(src).expect("ERROR: Unable to parse source").into_env();
+    let mut ev = Evaluator::new(&mut env);
+    let events: Vec<Event> = ev.events().collect();
+    assert_eq!(events.iter().filter(|e| matches!(**e, Event::Input { .. })).count(), 1);
+    assert!(!events.iter().any(|e| matches!(*e, Event::Finished { .. })));
+
+    assert!(ev.submit_input("Ann".into()).is_ok());
+    let lines: Vec<Vec<Var>> = ev.by_ref().map(|(vars,_)| vars).collect();
+    assert_eq!(lines, vec![vec!["Hi".into()]]);
+
+    assert!(Parser::parse_blocks("root\n    next:input player.age\n;").is_err());
+    assert!(Parser::parse_blocks("root\n    next:input @name\n;").is_err());
+    assert!(Parser::parse_blocks("root\n    next:input @player.\n;").is_err());
+    assert!(Parser::parse_blocks("root\n    next:input @player.name pattern \"[a-z\"\n;").is_err());
+}
+
+#[test]
+fn host_commands() {
+    let src = "root\n
+    emit \"The door creaks\"\n
+    do play_sound \"door.ogg\" sound.volume\n
+    do give_item \"{player.name}'s key\"\n
+    do shake_camera\n
+;\n
+def sound\n
+    volume 0.5\n
+;\n
+def player\n
+    name \"Ann\"\n
+;";
+
+    let mut env = Parser::parse_blocks(src).expect("ERROR: Unable to parse source").into_env();
+    env.commands.register("play_sound", Arity::Range(1,2));
+    env.commands.register("give_item", Arity::Exact(1));
+    env.commands.register("shake_camera", Arity::Exact(1));
+
+    let errors = env.validate_commands().unwrap_err();
+    assert_eq!(errors.len(), 1);
+    assert_eq!((&errors[0].name[..], errors[0].reason), ("shake_camera", "Wrong number of command args"));
+    assert_eq!(errors[0].line, 9); // NOTE: each line above ends in two newlines
+
+    env.commands.register("shake_camera", Arity::AtLeast(0));
+    assert!(env.validate_commands().is_ok());
+
+    let mut ev = Evaluator::new(&mut env);
+    let commands: Vec<(String, Vec<Var>)> = ev.events().filter_map(|e| match e {
+        Event::Command { name, args, .. } => Some((name, args)),
+        _ => None,
+    }).collect();
+
+    assert_eq!(commands, vec![("play_sound".to_owned(), vec!["door.ogg".into(), Var::Num(0.5)]),
+                              ("give_item".to_owned(), vec!["Ann's key".into()]),
+                              ("shake_camera".to_owned(), vec![])]);
+
+    // commands step with no vars through the tuple iterator
+    let mut env = Parser::parse_blocks(src).expect("ERROR: Unable to parse source").into_env();
+    let mut ev = Evaluator::new(&mut env);
+    let _ = ev.next().unwrap();
+    let (vars, next) = ev.next().unwrap();
+    assert!(vars.is_empty() && next.is_none());
+    assert_eq!(ev.command(), Some(&Command { name: "play_sound".to_owned(),
+                                             args: vec!["door.ogg".into(), Var::Num(0.5)] }));
+
+    assert!(Parser::parse_blocks("root\n    do\n;").is_err());
+    assert!(Parser::parse_blocks("root\n    do \"play_sound\"\n;").is_err());
+}
+
+#[test]
+fn speaker_lines() {
+    let src = "root\n
+    say guard.name \"Halt, {player.name}!\" #line:halt #mood:angry\n
+    Ann: \"Just passing through\"\n
+;\n
+def guard\n
+    name \"Gareth\"\n
+;\n
+def player\n
+    name \"Ann\"\n
+;";
+
+    let mut env = Parser::parse_blocks(src).expect("ERROR: Unable to parse source").into_env();
+    let mut ev = Evaluator::new(&mut env);
+
+    let _ = ev.next_event(); // entered
+    assert_eq!(ev.next_event(), Some(Event::Say { node: "root".to_owned(),
+                                                  idx: 0,
+                                                  speaker: "Gareth".to_owned(),
+                                                  text: "Halt, Ann!".to_owned(),
+                                                  id: Some("halt".to_owned()),
+                                                  tags: vec!["mood:angry".to_owned()] }));
+    match ev.next_event() {
+        Some(Event::Say { speaker, text, id, tags, .. }) => {
+            assert_eq!((&speaker[..], &text[..]), ("Ann", "Just passing through"));
+            assert!(id.is_some() && tags.is_empty());
+        },
+        e => panic!("ERROR: Expected say, got {:?}", e),
+    }
+
+    // speakers step as the first var through the tuple iterator
+    let mut env = Parser::parse_blocks(src).expect("ERROR: Unable to parse source").into_env();
+    let mut ev = Evaluator::new(&mut env);
+    let (vars,_) = ev.next().unwrap();
+    assert_eq!(vars, vec!["Gareth".into(), "Halt, Ann!".into()]);
+    assert_eq!(ev.line_ids(), &[None, Some("halt".to_owned())]);
+
+    assert!(Parser::parse_blocks("root\n    say \"Gareth\" \"Halt!\"\n;").is_err());
+    assert!(Parser::parse_blocks("root\n    say guard.name\n;").is_err());
+}
+
+#[test]
+fn line_and_node_tags() {
+    let src = "root #portrait:left #skip_in_demo\n
+    emit \"Hello\" #mood:angry #line:hello\n
+    # a plain comment, see #42 #todo\n
+    emit \"Bye\" # fixme #later\n
+;\n
+shop #portrait:right\n
+    emit \"Welcome\"\n
+;";
+
+    let mut env = Parser::parse_blocks(src).expect("ERROR: Unable to parse source").into_env();
+    assert_eq!(env.tagged("skip_in_demo"), vec!["root"]);
+    assert_eq!(env.tagged("portrait"), vec!["root", "shop"]);
+    assert!(env.tagged("portrait:up").is_empty());
+    assert!(env.tagged("todo").is_empty() && env.tagged("later").is_empty());
+    assert_eq!(env.node_tags("shop"), Some(&["portrait:right".to_owned()][..]));
+    assert_eq!(env.src["root"].line_tags(0), &["mood:angry".to_owned()]);
+    assert!(env.src["root"].line_tags(1).is_empty());
+
+    let mut ev = Evaluator::new(&mut env);
+    assert_eq!(ev.next_event(), Some(Event::NodeEntered { node: "root".to_owned(),
+                                                          idx: 0,
+                                                          tags: vec!["portrait:left".to_owned(),
+                                                                     "skip_in_demo".to_owned()] }));
+    assert_eq!(ev.next_event(), Some(Event::Line { node: "root".to_owned(),
+                                                   idx: 0,
+                                                   vars: vec!["Hello".into()],
+                                                   ids: vec![Some("hello".to_owned())],
+                                                   tags: vec!["mood:angry".to_owned()] }));
+    match ev.next_event() {
+        Some(Event::Line { tags, .. }) => assert!(tags.is_empty()),
+        e => panic!("ERROR: Expected line, got {:?}", e),
+    }
+}
+
+#[test]
+fn node_guards() {
+    let src = "root\n
+    next:call shop\n
+    emit \"Back outside\"\n
+    next:now vault\n
+;\n
+shop [requires has_money else broke]\n
+    has_money player.gold > 10\n
+    emit \"Welcome, buyer\"\n
+;\n
+broke\n
+    emit \"Come back with coin\"\n
+;\n
+vault [requires player.key] #locked\n
+    emit \"Gold!\"\n
+;\n
+def player\n
+    gold 5\n
+    key false\n
+;";
+
+    let mut env = Parser::parse_blocks(src).expect("ERROR: Unable to parse source").into_env();
+    assert_eq!(env.node_tags("vault"), Some(&["locked".to_owned()][..]));
+    {
+        let mut ev = Evaluator::new(&mut env);
+        let events: Vec<Event> = ev.events().collect();
+        assert!(events.contains(&Event::Refused { node: "shop".to_owned(),
+                                                  idx: 0,
+                                                  guard: "has_money".to_owned(),
+                                                  fallback: Some("broke".to_owned()) }));
+        assert!(events.contains(&Event::Refused { node: "vault".to_owned(),
+                                                  idx: 0,
+                                                  guard: "player.key".to_owned(),
+                                                  fallback: None }));
+
+        // the fallback is called in place of the shop, returning afterwards
+        let lines: Vec<Vec<Var>> = events.into_iter().filter_map(|e| match e {
+            Event::Line { vars, .. } => Some(vars),
+            _ => None,
+        }).collect();
+        assert_eq!(lines, vec![vec!["Come back with coin".into()], vec!["Back outside".into()]]);
+        assert_eq!(ev.session().visits("shop"), 0);
+
+        assert_eq!(ev.check_guard("vault").unwrap_err().requires, "player.key");
+        assert!(ev.check_guard("broke").is_ok());
+    }
+
+    env.def.get_mut("player").unwrap().data.insert("gold".to_owned(), Var::Int(20));
+    env.def.get_mut("player").unwrap().data.insert("key".to_owned(), Var::Bool(true));
+
+    // advancing checks the guard too
+    let mut ev = Evaluator::new(&mut env);
+    ev.advance("shop".to_owned());
+    let (vars,_) = ev.next().unwrap();
+    assert_eq!(vars, vec!["Welcome, buyer".into()]);
+    ev.advance("vault".to_owned());
+    let (vars,_) = ev.next().unwrap();
+    assert_eq!(vars, vec!["Gold!".into()]);
+
+    assert!(Parser::parse_blocks("shop [needs has_money]\n    emit \"Hi\"\n;").is_err());
+    assert!(Parser::parse_blocks("shop [requires has_money else]\n    emit \"Hi\"\n;").is_err());
+}
+
+#[test]
+fn signal_handlers() {
+    let src = "root\n
+    emit \"Walking\"\n
+    emit \"Still walking\"\n
+;\n
+creak [on door_opened]\n
+    emit \"The door creaks\"\n
+;\n
+draft [on door_opened priority 5]\n
+    emit \"A cold draft\"\n
+;\n
+forest [on enter_area \"forest\" requires first_time]\n
+    first_time forest.visits < 1\n
+    emit \"Tall trees\"\n
+;\n
+cave [on enter_area \"cave\"]\n
+    emit \"Darkness\"\n
+;";
+
+    let mut env = Parser::parse_blocks(src).expect("ERROR: Unable to parse source").into_env();
+    let mut ev = Evaluator::new(&mut env);
+
+    let (vars,_) = ev.next().unwrap();
+    assert_eq!(vars, vec!["Walking".into()]);
+
+    // handlers interrupt, highest priority first, then root picks up again
+    assert_eq!(ev.signal("door_opened", &[]), vec!["draft", "creak"]);
+    let lines: Vec<Vec<Var>> = ev.by_ref().map(|(vars,_)| vars).collect();
+    assert_eq!(lines, vec![vec!["A cold draft".into()],
+                           vec!["The door creaks".into()],
+                           vec!["Still walking".into()]]);
+
+    // a finished evaluator wakes up for a handler, args must match
+    assert_eq!(ev.signal("enter_area", &["forest".into(), Var::Int(3)]), vec!["forest"]);
+    let (vars,_) = ev.next().unwrap();
+    assert_eq!(vars, vec!["Tall trees".into()]);
+    assert!(ev.next().is_none());
+
+    // guards are checked as the handler is entered
+    assert_eq!(ev.signal("enter_area", &["forest".into()]), vec!["forest"]);
+    let events: Vec<Event> = ev.events().collect();
+    assert!(events.iter().any(|e| matches!(*e, Event::Refused { ref node, .. } if node == "forest")));
+    assert!(ev.signal("unknown", &[]).is_empty());
+
+    assert!(Parser::parse_blocks("a [on]\n    emit \"A\"\n;").is_err());
+}
+
+#[test]
+fn signal_keeps_pending() {
+    let src = "root\n
+    next:select 10 {\"A\" a, \"B\" b} timeout late\n
+    emit \"Past the menu\"\n
+;\n
+a\n
+    emit \"Picked A\"\n
+;\n
+door [on door]\n
+    emit \"A knock\"\n
+;";
+
+    let mut env = Parser::parse_blocks(src).expect("ERROR: Unable to parse source").into_env();
+    let mut ev = Evaluator::new(&mut env);
+    let (_,next) = ev.next().unwrap();
+    assert!(matches!(next, Some(Next::Select(_))));
+    ev.tick(4);
+
+    // the menu is put aside while the handler runs, then yielded again
+    assert_eq!(ev.signal("door", &[]), vec!["door"]);
+    let (vars,_) = ev.next().unwrap();
+    assert_eq!(vars, vec!["A knock".into()]);
+    assert!(ev.choices().is_empty());
+    let (vars,next) = ev.next().unwrap();
+    assert!(vars.is_empty());
+    assert!(matches!(next, Some(Next::Select(_))));
+    assert_eq!(ev.choices().len(), 2);
+    assert_eq!(ev.timeout(), Some(6));
+
+    assert!(ev.choose(0).is_ok());
+    let (vars,_) = ev.next().unwrap();
+    assert_eq!(vars, vec!["Picked A".into()]);
+
+    // event stream yields the choices again
+    let mut env = Parser::parse_blocks(src).expect("ERROR: Unable to parse source").into_env();
+    let mut ev = Evaluator::new(&mut env);
+    let is_choices = |e: &Event| matches!(*e, Event::Choices { .. });
+    while !ev.next_event().map_or(true, |e| is_choices(&e)) {}
+    ev.signal("door", &[]);
+    let events: Vec<Event> = ev.events().take_while(|e| !is_choices(e)).collect();
+    assert!(events.iter().any(|e| matches!(*e, Event::Line { ref node, .. } if node == "door")));
+    assert_eq!(ev.choices().len(), 2);
+    assert!(ev.choose(1).is_ok());
+}
+
+#[test]
+fn spawned_threads() {
+    let src = "root\n
+    next:spawn ambient\n
+    emit \"Hello\"\n
+    emit \"Goodbye\"\n
+;\n
+ambient\n
+    emit \"Birds sing\"\n
+    emit \"Wind blows\"\n
+;";
+
+    // main thread runs first, the spawned thread once it has nothing left
+    let mut env = Parser::parse_blocks(src).expect("ERROR: Unable to parse source").into_env();
+    let mut ev = Evaluator::new(&mut env);
+    let lines: Vec<Vec<Var>> = ev.by_ref().map(|(vars,_)| vars).filter(|v| !v.is_empty()).collect();
+    assert_eq!(lines, vec![vec!["Hello".into()],
+                           vec!["Goodbye".into()],
+                           vec!["Birds sing".into()],
+                           vec!["Wind blows".into()]]);
+    assert_eq!(ev.threads(), vec!["main"]);
+
+    // round-robin interleaves threads, events are tagged with theirs
+    let mut env = Parser::parse_blocks(src).expect("ERROR: Unable to parse source").into_env();
+    let mut ev = Evaluator::new(&mut env);
+    ev.set_schedule(Schedule::RoundRobin);
+
+    let mut lines = vec![];
+    while let Some((thread, e)) = ev.next_thread_event() {
+        if let Event::Line { vars, .. } = e {
+            lines.push((thread, vars[0].to_string()));
+        }
+    }
+    // NOTE: the spawn itself took main's first turn
+    assert_eq!(lines, vec![("ambient".to_owned(), "Birds sing".to_owned()),
+                           ("main".to_owned(), "Hello".to_owned()),
+                           ("ambient".to_owned(), "Wind blows".to_owned()),
+                           ("main".to_owned(), "Goodbye".to_owned())]);
+
+    // threads can be spawned and stepped by the host, by name
+    let mut env = Parser::parse_blocks(src).expect("ERROR: Unable to parse source").into_env();
+    let mut ev = Evaluator::new(&mut env);
+    assert!(ev.spawn("birds", "ambient"));
+    assert!(!ev.spawn("birds", "ambient"));
+    assert!(!ev.spawn("main", "ambient"));
+    assert_eq!(ev.threads(), vec!["main", "birds"]);
+
+    let (vars,_) = ev.step_thread("birds").unwrap();
+    assert_eq!(vars, vec!["Birds sing".into()]);
+    let (vars,_) = ev.step_thread("birds").unwrap();
+    assert_eq!(vars, vec!["Wind blows".into()]);
+    assert!(ev.step_thread("birds").is_none());
+    assert_eq!(ev.threads(), vec!["main"]);
+    assert!(ev.step_thread("unknown").is_none());
+
+    assert!(Parser::parse_blocks("root\n    next:spawn\n;").is_err());
+}
+
+#[test]
+fn threads_keep_places() {
+    let src = "root\n
+    next:spawn chat one\n
+    next:spawn chat two\n
+    emit \"m1\"\n
+;\n
+chat\n
+    emit \"c1\"\n
+    emit \"c2\"\n
+    emit \"c3\"\n
+;";
+
+    // the same node runs on two threads, each from its own place
+    let mut env = Parser::parse_blocks(src).expect("ERROR: Unable to parse source").into_env();
+    let mut ev = Evaluator::new(&mut env);
+    ev.set_schedule(Schedule::RoundRobin);
+
+    let mut lines: HashMap<String,Vec<String>> = HashMap::new();
+    while let Some((thread, e)) = ev.next_thread_event() {
+        if let Event::Line { vars, .. } = e {
+            lines.entry(thread).or_default().push(vars[0].to_string());
+        }
+    }
+    assert_eq!(lines["one"], vec!["c1", "c2", "c3"]);
+    assert_eq!(lines["two"], vec!["c1", "c2", "c3"]);
+    assert_eq!(lines["main"], vec!["m1"]);
+
+    // the schedule is saved along with the rest
+    let mut env = Parser::parse_blocks(src).expect("ERROR: Unable to parse source").into_env();
+    let state = {
+        let mut ev = Evaluator::new(&mut env);
+        ev.set_schedule(Schedule::RoundRobin);
+        ev.save()
+    };
+    let mut ev = state.to_eval(&mut env);
+    let first = std::iter::from_fn(|| ev.next_thread_event())
+        .find(|&(_, ref e)| matches!(*e, Event::Line { .. }));
+    assert_eq!(first.map(|(thread, _)| thread), Some("one".to_owned()));
+}
+
+#[test]
+fn threads_keep_choices() {
+    let src = "root\n
+    next:spawn chatter\n
+    next:select 5 {\"Stay\" stay, \"Go\" go} timeout stay\n
+;\n
+chatter\n
+    emit \"Nice weather\"\n
+    emit \"Shall we?\"\n
+;\n
+go\n
+    emit \"Off we go\"\n
+;";
+
+    let mut env = Parser::parse_blocks(src).expect("ERROR: Unable to parse source").into_env();
+    let mut ev = Evaluator::new(&mut env);
+    ev.set_schedule(Schedule::RoundRobin);
+
+    ev.next(); // spawn
+    let (vars,_) = ev.next().unwrap();
+    assert_eq!(vars, vec!["Nice weather".into()]);
+    let (_,next) = ev.next().unwrap();
+    assert!(matches!(next, Some(Next::Select(_))));
+
+    // the companion speaks while main's menu stays up
+    let (vars,_) = ev.next().unwrap();
+    assert_eq!(vars, vec!["Shall we?".into()]);
+    assert_eq!(ev.choices().len(), 2);
+    assert_eq!(ev.timeout(), Some(5));
+
+    ev.choose(1).unwrap();
+    assert!(ev.choices().is_empty());
+    assert_eq!(ev.timeout(), None);
+    let lines: Vec<Vec<Var>> = ev.by_ref().map(|(vars,_)| vars).collect();
+    assert_eq!(lines, vec![vec!["Off we go".into()]]);
+}
+
+#[test]
+fn tick_timeouts() {
+    let src = "root\n
+    emit \"A bandit draws a knife\"\n
+    next:select 10 {\"Fight\" fight, \"Talk\" talk} timeout flee\n
+;\n
+flee\n
+    emit \"You run\"\n
+    wait 5\n
+    emit \"Out of breath\"\n
+    next:await 3 camp timeout woods\n
+;\n
+fight\n
+    emit \"You fight\"\n
+;\n
+woods\n
+    emit \"Lost in the woods\"\n
+;";
+
+    let mut env = Parser::parse_blocks(src).expect("ERROR: Unable to parse source").into_env();
+    let mut ev = Evaluator::new(&mut env);
+
+    ev.next();
+    let (_,next) = ev.next().unwrap();
+    assert!(matches!(next, Some(Next::Select(_))));
+    assert_eq!(ev.timeout(), Some(10));
+    assert_eq!(ev.choices().len(), 2);
+
+    // hesitating heads to the timeout's node
+    assert_eq!(ev.tick(4), None);
+    assert_eq!(ev.timeout(), Some(6));
+    assert_eq!(ev.tick(6), Some("flee".to_owned()));
+    assert!(ev.choices().is_empty());
+    assert_eq!(ev.timeout(), None);
+
+    let events: Vec<Event> = ev.events().collect();
+    assert!(events.contains(&Event::TimedOut { node: "root".to_owned(), idx: 1, target: "flee".to_owned() }));
+    assert!(events.contains(&Event::Wait { node: "flee".to_owned(), idx: 1, ticks: 5 }));
+
+    // nothing is emitted while waiting
+    assert_eq!(ev.waiting("main"), 5);
+    assert!(ev.next_event().is_none());
+    assert_eq!(ev.tick(5), None);
+    assert_eq!(ev.waiting("main"), 0);
+
+    let mut lines = vec![];
+    while let Some(e) = ev.next_event() {
+        match e {
+            Event::Line { vars, .. } => lines.push(vars[0].to_string()),
+            Event::AwaitAdvance { .. } => break,
+            _ => {},
+        }
+    }
+    assert_eq!(lines, vec!["Out of breath".to_owned()]);
+    assert_eq!(ev.timeout(), Some(3));
+    assert_eq!(ev.tick(3), Some("woods".to_owned()));
+
+    let (vars,_) = ev.next().unwrap();
+    assert_eq!(vars, vec!["Lost in the woods".into()]);
+
+    // choosing in time stops the timeout
+    let mut env = Parser::parse_blocks(src).expect("ERROR: Unable to parse source").into_env();
+    let mut ev = Evaluator::new(&mut env);
+    ev.next(); ev.next();
+    ev.choose(0).unwrap();
+    assert_eq!(ev.tick(10), None);
+    let (vars,_) = ev.next().unwrap();
+    assert_eq!(vars, vec!["You fight".into()]);
+
+    // saved state keeps timeouts and waits counting down
+    let mut env = Parser::parse_blocks(src).expect("ERROR: Unable to parse source").into_env();
+    let mut ev = Evaluator::new(&mut env);
+    ev.next(); ev.next();
+    assert_eq!(ev.tick(4), None);
+
+    let mut ev = ev.save().to_eval(&mut env);
+    assert_eq!(ev.choices().len(), 2);
+    assert_eq!(ev.timeout(), Some(6));
+    assert_eq!(ev.tick(6), Some("flee".to_owned()));
+    ev.next(); ev.next(); // wait
+    assert_eq!(ev.tick(2), None);
+
+    let mut ev = ev.save().to_eval(&mut env);
+    assert_eq!(ev.waiting("main"), 3);
+    assert!(ev.next().is_none());
+    ev.tick(3);
+    let (vars,_) = ev.next().unwrap();
+    assert_eq!(vars, vec!["Out of breath".into()]);
+
+    // timed awaits head to the awaited node unless named
+    let src = "root\n    next:await 2 inn\n;\ninn\n    emit \"Warm\"\n;";
+    let mut env = Parser::parse_blocks(src).expect("ERROR: Unable to parse source").into_env();
+    let mut ev = Evaluator::new(&mut env);
+    let (_,next) = ev.next().unwrap();
+    assert!(matches!(next, Some(Next::AwaitFor(ref nn, _)) if nn == "inn"));
+    assert_eq!(ev.tick(2), Some("inn".to_owned()));
+
+    assert!(Parser::parse_blocks("root\n    next:select 10 {\"A\" a}\n;").is_err());
+    assert!(Parser::parse_blocks("root\n    next:select {\"A\" a} timeout b\n;").is_err());
+    assert!(Parser::parse_blocks("root\n    wait\n;").is_err());
+}
@@ -0,0 +1,170 @@
+extern crate lichen;
+
+use lichen::parse::Parser;
+use lichen::eval::Evaluator;
+use lichen::locale::{self,Locale,line_id};
+use lichen::source::Next;
+use lichen::po;
+
+#[test]
+fn extract_lines() {
+    let src = "root\n
+    emit \"Hello, {player.name}\" 5\n
+    if !player.gold \"You look broke\"\n
+    or [\"Nice purse\" next:select {\"Store\" store, \"Leave\" exit}]\n
+;\n
+store\n
+    emit \"Welcome\"\n
+;";
+
+    let env = Parser::parse_blocks(src).expect("ERROR: Unable to parse source").into_env();
+    let lines = env.lines();
+
+    let texts: Vec<(&str,&str,usize)> = lines.iter()
+        .map(|l| (&l.node[..], &l.text[..], l.idx)).collect();
+    assert!(texts.contains(&("root", "Hello, {player.name}", 0)));
+    assert!(texts.contains(&("root", "You look broke", 2)));
+    assert!(texts.contains(&("root", "Nice purse", 3)));
+    assert!(texts.contains(&("root", "Store", 3)));
+    assert!(texts.contains(&("root", "Leave", 3)));
+    assert_eq!(texts.last(), Some(&("store", "Welcome", 0)));
+    assert_eq!(lines.len(), 6);
+
+    assert_eq!(lines[0].id, line_id("root", "Hello, {player.name}"));
+    assert_eq!(line_id("root", "Welcome"), line_id("root", "Welcome"));
+    assert!(line_id("root", "Welcome") != line_id("store", "Welcome"));
+}
+
+#[test]
+fn translate_lines() {
+    let src = "root\n
+    emit \"Hello, {player.name}\"\n
+    emit \"Untranslated\"\n
+    next:select {\"Store\" store, \"Leave\" exit}\n
+;\n
+def player\n
+    name \"Io\"\n
+;";
+
+    let mut env = Parser::parse_blocks(src).expect("ERROR: Unable to parse source").into_env();
+
+    let table = format!("# french\n{} \"Bonjour, {{player.name}}\"\n{} \"Magasin\"\n",
+                        line_id("root", "Hello, {player.name}"),
+                        line_id("root", "Store"));
+    let locale = Locale::parse("fr", &table).expect("ERROR: Unable to parse locale");
+    env.insert_locale(locale);
+    env.locale = "fr".to_owned();
+
+    let mut ev = Evaluator::new(&mut env);
+    let (vars,_) = ev.next().unwrap();
+    assert_eq!(vars[0], "Bonjour, Io".into());
+
+    let (vars,_) = ev.next().unwrap();
+    assert_eq!(vars[0], "Untranslated".into()); // falls back to source
+
+    let (_,next) = ev.next().unwrap();
+    match next {
+        Some(Next::Select(select)) => {
+            assert!(select.map.contains_key("Magasin"));
+            assert!(select.map.contains_key("Leave"));
+        },
+        _ => panic!("ERROR: Select was not returned"),
+    }
+}
+
+#[test]
+fn parse_locale_errors() {
+    assert!(Locale::parse("fr", "root-1 \"ok\"\nroot-2\n").is_err());
+    assert!(Locale::parse("fr", "root-1 \"unclosed").is_err());
+
+    let locale = Locale::parse("fr", "root-1 \"multi\nline\" # comment\n").unwrap();
+    assert_eq!(locale.get("root-1"), Some("multi\nline"));
+}
+
+#[test]
+fn po_roundtrip() {
+    let src = "root\n
+    emit \"Hello, {player.name}\"\n
+    emit \"Say hi to `player.name\"\n
+    emit \"Hello, {player.name}\"\n
+    next:now store\n
+;\n
+store\n
+    emit \"Hello, {player.name}\"\n
+;\n
+def player\n
+    name \"Io\"\n
+;";
+
+    let mut env = Parser::parse_blocks(src).expect("ERROR: Unable to parse source").into_env();
+    let pot = po::write_pot(&env);
+
+    let hello = line_id("root", "Hello, {player.name}");
+    let entry = format!("#. placeholders: {{player.name}}\n#: root:3 root:7\nmsgctxt \"{}\"\nmsgid \"Hello, {{player.name}}\"\nmsgstr \"\"\n", hello);
+    assert!(pot.contains(&entry), "{}", pot);
+    assert!(pot.contains("#: store:15\n"));
+
+    // a translator fills in the template, leaving the store entry as fuzzy
+    let store = format!("msgctxt \"{}\"\nmsgid \"Hello, {{player.name}}\"\nmsgstr \"\"", line_id("store", "Hello, {player.name}"));
+    let po = pot.replace(&store, &format!("#, fuzzy\n{}", store.replace("msgstr \"\"", "msgstr \"Bonjour\"")));
+    let po = po.replacen("msgid \"Hello, {player.name}\"\nmsgstr \"\"",
+                         "msgid \"Hello, {player.name}\"\nmsgstr \"Salut, \"\n\"{player.name}\"", 1);
+
+    let locale = po::read_po("fr", &po).expect("ERROR: Unable to read po");
+    assert_eq!(locale.get(&hello), Some("Salut, {player.name}"));
+    assert_eq!(locale.lines.len(), 1); // untranslated and fuzzy entries are skipped
+
+    env.insert_locale(locale);
+    env.locale = "fr".to_owned();
+    let mut ev = Evaluator::new(&mut env);
+    let (vars,_) = ev.next().unwrap();
+    assert_eq!(vars[0], "Salut, Io".into());
+}
+
+#[test]
+fn stable_line_ids() {
+    let src = "root\n
+    emit \"Hello there\" \"Second\" #line:greet\n
+    emit \"Edited text\"\n
+;";
+
+    let mut env = Parser::parse_blocks(src).expect("ERROR: Unable to parse source").into_env();
+    let lines = env.lines();
+    assert_eq!(lines[0].id, "greet");
+    assert_eq!(lines[1].id, "greet.1");
+    assert_eq!(lines[2].id, line_id("root", "Edited text"));
+
+    let locale = Locale::parse("fr", "greet \"Salut\"\n").expect("ERROR: Unable to parse locale");
+    env.insert_locale(locale);
+    env.locale = "fr".to_owned();
+
+    let mut ev = Evaluator::new(&mut env);
+    let (vars,_) = ev.next().unwrap();
+    assert_eq!(vars, vec!["Salut".into(), "Second".into()]);
+    assert_eq!(ev.line_ids(), &[Some("greet".to_owned()), Some("greet.1".to_owned())]);
+}
+
+#[test]
+fn write_line_ids() {
+    let src = "root\n    emit \"Hi\" # a comment\n    if tired \"Long\n    text\"\n    emit \"Kept\" #line:kept\n;";
+
+    let tagged = locale::write_line_ids(src).expect("ERROR: Unable to write line IDs");
+    let expected = format!("root\n    emit \"Hi\" # a comment #line:{}\n    if tired \"Long\n    text\" #line:{}\n    emit \"Kept\" #line:kept\n;",
+                           line_id("root", "Hi"), line_id("root", "Long\n    text"));
+    assert_eq!(tagged, expected);
+
+    // tagging is stable, and ids survive later edits to the text
+    assert_eq!(locale::write_line_ids(&tagged).unwrap(), tagged);
+    let edited = tagged.replace("\"Hi\"", "\"Hello\"");
+    let env = Parser::parse_blocks(&edited).unwrap().into_env();
+    assert_eq!(env.lines()[0].id, line_id("root", "Hi"));
+
+    // every string keeps its ID, not just the first of a statement
+    let src = "root\n    emit [\"one\" \"two\"]\n    emit:cycle \"a\" \"b\"\n    next:select {\"Go\" root}\n;";
+    let ids = |src: &str| -> Vec<String> {
+        Parser::parse_blocks(src).unwrap().into_env().lines().into_iter().map(|l| l.id).collect()
+    };
+    let tagged = locale::write_line_ids(src).unwrap();
+    assert_eq!(ids(&tagged), ids(src));
+    assert!(tagged.contains(&format!("#line:{} #line:{}", line_id("root", "one"), line_id("root", "two"))));
+}
@@ -1,16 +1,19 @@
 extern crate lichen;
 
 use lichen::parse::Parser;
-use lichen::var::Var;
+use lichen::var::{Var,Float};
 use lichen::eval::Evaluator;
 use lichen::fun::Fun;
+use lichen::rng::Rng;
+use lichen::bark::Barks;
 
 use std::sync::{Arc,Mutex};
+use std::collections::HashMap;
 
 // Test for mutable state
 #[derive(Debug)]
 struct Player {
-    coins: f32,
+    coins: Float,
     name: String
 }
 
@@ -205,3 +208,430 @@ def global\n
     let (vars,_) = ev.next().unwrap();
     assert_eq!(vars[0], "is drunk".into());
 }
+
+#[test]
+fn int_math() {
+    let src = "root\n
+    @global.gold + 16777217\n
+    @global.half / 2\n
+    @global.third / 3.0\n
+    @global.broke / 0\n
+    @global.rich + 9223372036854775807\n
+    emit global.gold global.half global.third global.broke global.rich\n
+;\n
+def global\n
+    gold 1\n
+    half 7\n
+    third 1\n
+    broke 5\n
+    rich 1\n
+;";
+
+    let mut env = Parser::parse_blocks(src).expect("ERROR: Unable to parse source").into_env();
+
+    let mut ev = Evaluator::new(&mut env);
+    let (vars,_) = ev.next().unwrap();
+
+    match vars[0] {
+        Var::Int(n) => assert_eq!(n, 16777218),
+        _ => panic!("ERROR: Int was not kept on addition"),
+    }
+    assert_eq!(vars[1], Var::Int(3)); // truncated
+    assert_eq!(vars[2], Var::Num(1. / 3.)); // promoted
+    assert_eq!(vars[3], Var::Int(5)); // untouched
+    assert_eq!(vars[4], Var::Int(i64::MAX)); // saturated
+    assert_eq!(vars[0].to_string(), "16777218");
+}
+
+#[test]
+fn unset_and_delete() {
+    let src = "root\n
+    has_name exists global.name\n
+    no_name is_nil global.name\n
+    no_title is_nil global.title\n
+    comp:all [has_name no_title]\n
+    if comp \"named\"\n
+\n
+    @global.name unset\n
+    if no_name \"unnamed\"\n
+\n
+    @global.title nil\n
+    if global.title \"titled\"\n
+    or \"untitled\"\n
+\n
+    @player delete\n
+    if !player.coins \"deleted\"\n
+;\n
+def global\n
+    name \"Io\"\n
+;\n
+def player\n
+    coins 5\n
+;";
+
+    let mut env = Parser::parse_blocks(src).expect("ERROR: Unable to parse source").into_env();
+
+    {
+        let mut ev = Evaluator::new(&mut env);
+        let (vars,_) = ev.next().unwrap();
+        assert_eq!(vars[0], "named".into());
+        let (vars,_) = ev.next().unwrap();
+        assert_eq!(vars[0], "unnamed".into());
+        let (vars,_) = ev.next().unwrap();
+        assert_eq!(vars[0], "untitled".into());
+        let (vars,_) = ev.next().unwrap();
+        assert_eq!(vars[0], "deleted".into());
+    }
+
+    assert!(!env.def["global"].data.contains_key("name"));
+    assert_eq!(env.def["global"].data.get("title"), Some(&Var::Nil));
+    assert!(!env.def.contains_key("player"));
+}
+
+#[test]
+fn visit_counts() {
+    let src = "root\n
+    next:select {\"Tavern\" tavern, \"Street\" street}\n
+;\n
+tavern\n
+    regular tavern.visits > 1\n
+    if regular \"Welcome back\"\n
+    or \"A new face\"\n
+    next:now root\n
+;\n
+street\n
+    recent turns_since(tavern) < 2\n
+    if recent \"The tavern is close by\"\n
+    emit \"Turn {turns}, tavern visits {tavern.visits}\"\n
+;";
+
+    let mut env = Parser::parse_blocks(src).expect("ERROR: Unable to parse source").into_env();
+    let mut ev = Evaluator::new(&mut env);
+
+    let _ = ev.next().unwrap();
+    ev.choose(0).unwrap();
+    let (vars,_) = ev.next().unwrap();
+    assert_eq!(vars[0], "A new face".into());
+    let _ = ev.next().unwrap();
+    
+    let _ = ev.next().unwrap();
+    ev.choose(0).unwrap();
+    let (vars,_) = ev.next().unwrap();
+    assert_eq!(vars[0], "Welcome back".into());
+    let _ = ev.next().unwrap();
+
+    assert_eq!(ev.session().visits("tavern"), 2);
+    assert_eq!(ev.session().visits("root"), 2);
+    assert_eq!(ev.session().turns_since("tavern"), Some(0));
+    assert_eq!(ev.session().turns_since("street"), None);
+
+    let _ = ev.next().unwrap();
+    ev.choose(1).unwrap();
+    let (vars,_) = ev.next().unwrap();
+    assert_eq!(vars[0], "The tavern is close by".into());
+    let (vars,_) = ev.next().unwrap();
+    assert_eq!(vars[0], "Turn 3, tavern visits 2".into());
+
+    // counters are kept in saved state
+    let state = ev.save();
+    let ev = state.to_eval(&mut env);
+    assert_eq!(ev.session().turn, 3);
+}
+
+#[test]
+fn variations() {
+    let src = "root\n
+    emit:sequence \"Hello\" \"Hi again\" \"You again?\"\n
+    emit:cycle \"Morning\" \"Evening\"\n
+    emit:once \"Welcome\"\n
+    emit:shuffle 1 2 3 4 5\n
+    next:restart\n
+;";
+
+    let mut env = Parser::parse_blocks(src).expect("ERROR: Unable to parse source").into_env();
+    let mut ev = Evaluator::new(&mut env);
+    ev.seed(7);
+
+    let mut seen = vec![];
+    let mut shuffled = vec![];
+    for _ in 0..5 {
+        for (vars,next) in ev.by_ref() {
+            if next.is_some() { break }
+            match vars[0] {
+                Var::Int(n) => shuffled.push(n),
+                ref v => seen.push(v.to_string()),
+            }
+        }
+    }
+
+    assert_eq!(seen, vec!["Hello", "Morning", "Welcome",
+                          "Hi again", "Evening",
+                          "You again?", "Morning",
+                          "You again?", "Evening",
+                          "You again?", "Morning"]);
+
+    // each pass through a shuffle covers every alternative
+    let mut pass = shuffled.clone();
+    pass.sort();
+    assert_eq!(pass, vec![1, 2, 3, 4, 5]);
+
+    // shuffles replay when seeded the same, including after a save
+    let mut env2 = Parser::parse_blocks(src).unwrap().into_env();
+    let mut ev2 = Evaluator::new(&mut env2);
+    ev2.seed(7);
+    let mut replay = vec![];
+    for _ in 0..3 {
+        for (vars,next) in ev2.by_ref() {
+            if next.is_some() { break }
+            if let Var::Int(n) = vars[0] { replay.push(n); }
+        }
+    }
+
+    let mut ev2 = ev2.save().to_eval(&mut env2);
+    for _ in 0..2 {
+        for (vars,next) in ev2.by_ref() {
+            if next.is_some() { break }
+            if let Var::Int(n) = vars[0] { replay.push(n); }
+        }
+    }
+    assert_eq!(replay, shuffled);
+
+    assert!(Parser::parse_blocks("root\n    emit:sometimes \"Hi\"\n;").is_err());
+}
+
+const RANDOM_SRC: &str = "root\n
+    crit chance 0.5\n
+    never chance 0\n
+    @roll.value 2d6\n
+    @total.value + d4\n
+    if crit \"Critical hit\"\n
+    if never \"Never seen\"\n
+    emit roll.value total.value\n
+    next:random {left 3, right 1, nowhere 0}\n
+;\n
+left\n
+    emit \"left\"\n
+;\n
+right\n
+    emit \"right\"\n
+;\n
+def roll\n
+    value 0\n
+;\n
+def total\n
+    value 10\n
+;";
+
+fn random_run (seed: u64) -> Vec<Var> {
+    let mut env = Parser::parse_blocks(RANDOM_SRC).expect("ERROR: Unable to parse source").into_env();
+    let mut ev = Evaluator::new(&mut env);
+    ev.seed(seed);
+
+    let mut out = vec![];
+    for (vars,_) in ev.by_ref() { out.extend(vars); }
+    out
+}
+
+#[test]
+fn seeded_randomness() {
+    let mut crits = 0;
+    let mut lefts = 0;
+    for seed in 0..200 {
+        let out = random_run(seed);
+        assert_eq!(out, random_run(seed)); // replays with the same seed
+
+        let mut out = out.iter();
+        let mut v = out.next().unwrap();
+        if *v == "Critical hit".into() {
+            crits += 1;
+            v = out.next().unwrap();
+        }
+
+        match (v, out.next().unwrap()) {
+            (&Var::Int(roll), &Var::Int(total)) => {
+                assert!((2..=12).contains(&roll));
+                assert!((11..=14).contains(&total));
+            },
+            r => panic!("ERROR: Expected dice rolls, got {:?}", r),
+        }
+
+        match out.next().unwrap().to_string().as_str() {
+            "left" => lefts += 1,
+            "right" => {},
+            r => panic!("ERROR: Unexpected node {:?}", r),
+        }
+    }
+
+    assert!(crits > 50 && crits < 150);
+    assert!(lefts > 100 && lefts < 190);
+}
+
+#[test]
+fn dice_only_roll_as_numbers() {
+    let src = "root\n
+    @big.value 1000d18446744073709551615\n
+    if d6 \"Never a roll\"\n
+    @hit.value + d6\n
+    emit hit.value\n
+;\n
+def hit\n
+    value 0\n
+;";
+
+    let mut env = Parser::parse_blocks(src).expect("ERROR: Unable to parse source").into_env();
+    let mut ev = Evaluator::new(&mut env);
+    let out: Vec<Var> = ev.by_ref().flat_map(|(vars,_)| vars).collect();
+    match out[..] {
+        [Var::Int(hit)] => assert!((1..=6).contains(&hit)),
+        ref r => panic!("ERROR: Expected a single roll, got {:?}", r),
+    }
+}
+
+#[test]
+fn custom_rng() {
+    // always rolls the lowest
+    struct Low;
+    impl Rng for Low {
+        fn next_u64 (&mut self) -> u64 { 0 }
+        fn box_clone (&self) -> Box<dyn Rng> { Box::new(Low) }
+    }
+
+    let mut env = Parser::parse_blocks(RANDOM_SRC).expect("ERROR: Unable to parse source").into_env();
+    let mut ev = Evaluator::new(&mut env);
+    ev.set_rng(Box::new(Low));
+
+    let out: Vec<Var> = ev.by_ref().flat_map(|(vars,_)| vars).collect();
+    assert_eq!(out, vec!["Critical hit".into(), Var::Int(2), Var::Int(11), "left".into()]);
+}
+
+#[test]
+fn storylets() {
+    let src = "market [storylet priority 5 repeatable requires market_open] #town\n
+    market_open town.day > 1\n
+    emit \"The market bustles\"\n
+;\n
+ambush [storylet priority town.danger] #road\n
+    emit \"Bandits!\"\n
+;\n
+festival [storylet priority 5] #town\n
+    emit \"A festival!\"\n
+;\n
+rest [storylet]\n
+    emit \"You rest\"\n
+;\n
+def town\n
+    day 1\n
+    danger 9\n
+;";
+
+    let mut env = Parser::parse_blocks(src).expect("ERROR: Unable to parse source").into_env();
+    {
+        let mut ev = Evaluator::new(&mut env);
+        assert_eq!(ev.storylets(None), vec!["ambush", "festival", "rest"]);
+        assert_eq!(ev.storylets(Some("town")), vec!["festival"]);
+        assert_eq!(ev.start_storylet("market"), Err("Storylet is not eligible"));
+
+        assert!(ev.start_storylet("festival").is_ok());
+        let (vars,_) = ev.next().unwrap();
+        assert_eq!(vars, vec!["A festival!".into()]);
+
+        // only repeatable storylets stay eligible once visited
+        assert!(ev.storylets(Some("town")).is_empty());
+    }
+
+    env.def.get_mut("town").unwrap().data.insert("day".to_owned(), Var::Int(2));
+    env.def.get_mut("town").unwrap().data.insert("danger".to_owned(), Var::Int(1));
+    let mut ev = Evaluator::new(&mut env);
+    assert_eq!(ev.storylets(None), vec!["festival", "market", "ambush", "rest"]);
+
+    assert!(Parser::parse_blocks("market [priority 5]\n    emit \"Hi\"\n;").is_err());
+    assert!(Parser::parse_blocks("market [storylet priority]\n    emit \"Hi\"\n;").is_err());
+}
+
+#[test]
+fn bark_rules() {
+    let mut src = "guard_idle [bark]\n
+    is_guard fact.who == guard\n
+    emit \"Quiet night\"\n
+;\n
+guard_hurt [bark cooldown 10]\n
+    is_guard fact.who == guard\n
+    hurt fact.health < 30\n
+    emit \"Need a medic!\"\n
+;\n
+guard_hurt_night [bark]\n
+    is_guard fact.who == guard\n
+    hurt fact.health < 30\n
+    dark world.time == night\n
+    emit \"Can't see a thing, and I'm bleeding\"\n
+;\n
+anyone_hurt [bark]\n
+    dying fact.health < 5\n
+    emit \"Ow\"\n
+;\n
+def world\n
+    time day\n
+;".to_owned();
+
+    // plenty of rules for other speakers, kept out of the way by the index
+    for i in 0..2000 {
+        src.push_str(&format!("\nfarmer{} [bark]\n    is_farmer fact.who == farmer{}\n    hurt fact.health < 30\n    emit \"Ow\"\n;", i, i));
+    }
+
+    let mut env = Parser::parse_blocks(&src).expect("ERROR: Unable to parse source").into_env();
+    let mut barks = Barks::compile(&env);
+    assert_eq!(barks.len(), 2004);
+
+    let mut ev = Evaluator::new(&mut env);
+    let mut facts = HashMap::new();
+    facts.insert("who".to_owned(), Var::from("guard"));
+    facts.insert("health".to_owned(), Var::Int(20));
+
+    // the most specific rule wins, until it cools down
+    assert_eq!(ev.bark(&mut barks, &facts, 0), Some("guard_hurt".to_owned()));
+    assert_eq!(ev.bark(&mut barks, &facts, 5), Some("guard_idle".to_owned()));
+    assert_eq!(ev.bark(&mut barks, &facts, 10), Some("guard_hurt".to_owned()));
+
+    facts.insert("health".to_owned(), Var::Int(90));
+    assert_eq!(ev.bark(&mut barks, &facts, 20), Some("guard_idle".to_owned()));
+
+    facts.insert("who".to_owned(), Var::from("farmer7"));
+    assert_eq!(ev.bark(&mut barks, &facts, 20), None);
+    facts.insert("health".to_owned(), Var::Int(10));
+    assert_eq!(ev.bark(&mut barks, &facts, 20), Some("farmer7".to_owned()));
+
+    facts.insert("health".to_owned(), Var::Int(1));
+    assert_eq!(ev.bark(&mut barks, &facts, 20), Some("farmer7".to_owned()));
+    facts.insert("who".to_owned(), Var::from("nobody"));
+    assert_eq!(ev.bark(&mut barks, &facts, 20), Some("anyone_hurt".to_owned()));
+
+    // symbol facts match as their text does
+    facts.insert("who".to_owned(), Var::Sym("farmer7".to_owned()));
+    assert_eq!(ev.bark(&mut barks, &facts, 20), Some("farmer7".to_owned()));
+    facts.insert("who".to_owned(), Var::Sym("guard".to_owned()));
+    facts.insert("health".to_owned(), Var::Int(90));
+    assert_eq!(ev.bark(&mut barks, &facts, 20), Some("guard_idle".to_owned()));
+
+    // facts are only set for the query
+    let _ = ev.save();
+    assert!(!env.def.contains_key("fact"));
+
+    // equally specific rules are picked at random
+    let src = "a [bark]\n    emit \"A\"\n;\nb [bark]\n    emit \"B\"\n;";
+    let mut env = Parser::parse_blocks(src).expect("ERROR: Unable to parse source").into_env();
+    let mut barks = Barks::compile(&env);
+    let mut ev = Evaluator::new(&mut env);
+    ev.seed(3);
+    let picks: Vec<String> = (0..20).filter_map(|t| ev.bark(&mut barks, &HashMap::new(), t)).collect();
+    assert!(picks.iter().any(|p| p == "a") && picks.iter().any(|p| p == "b"));
+
+    // a cooldown too long to count out never ends
+    let src = "once [bark cooldown 18446744073709551615]\n    emit \"Once\"\n;";
+    let mut env = Parser::parse_blocks(src).expect("ERROR: Unable to parse source").into_env();
+    let mut barks = Barks::compile(&env);
+    let mut ev = Evaluator::new(&mut env);
+    assert_eq!(ev.bark(&mut barks, &HashMap::new(), 5), Some("once".to_owned()));
+    assert_eq!(ev.bark(&mut barks, &HashMap::new(), u64::MAX - 1), None);
+
+    assert!(Parser::parse_blocks("a [cooldown 5]\n    emit \"A\"\n;").is_err());
+}
@@ -1,10 +1,11 @@
 extern crate lichen;
 
-use lichen::parse::{Parser,Block,SrcBlock,Map};
+use lichen::parse::{Parser,Block,SrcBlock,SrcMeta,Map};
 use lichen::logic::{Logic,Expect};
 use lichen::var::{Var,Mut};
 use lichen::source::{Src,Next};
 use lichen::eval::Evaluator;
+use lichen::interp::{PluralRules,Plural};
 
 use std::collections::HashMap;
 
@@ -55,6 +56,13 @@ fn parse_block() {
                               None),
                       Src::Next(Next::Now("end".to_owned()))],
             logic: HashMap::new(),
+            meta: [3, 5, 5, 7, 9, 11, 18, 22, 25].iter()
+                .map(|&line| SrcMeta { line, ids: vec![], tags: vec![] }).collect(),
+            tags: vec![],
+            guard: None,
+            storylet: None,
+            bark: None,
+            on: None,
         })];
     
     assert_eq!(block[0],block_[0]);
@@ -270,18 +278,18 @@ fn parse_select_nodes() {
 
     assert_ne!(select1,select2);
     
-    let mut map: Map = HashMap::new();
+    let mut map = Map::new();
     map.insert("Head to Store?".to_owned(), vec![Var::Sym("store2".to_owned())]);
     map.insert("Leave the town?".to_owned(), vec![Var::Sym("exit-town2".to_owned())]);
     
-    assert_eq!(select2, Some(Next::Select(map)));
+    assert_eq!(select2, Some(Next::Select(map.into())));
 
     let (_,select) = ev.next().unwrap();
     match select.expect("Unable to parse map") {
-        Next::Select(map) => {
-            println!("Map: {:?}",map);
-            assert!(map.contains_key("5"));
-            assert_eq!(map.get("5"), Some(&vec![Var::Sym("hike".to_owned())]));
+        Next::Select(select) => {
+            println!("Map: {:?}",select.map);
+            assert!(select.map.contains_key("5"));
+            assert_eq!(select.map.get("5"), Some(&vec![Var::Sym("hike".to_owned())]));
         },
         _ => { panic!("Invalid Next type found") }
     }
@@ -332,6 +340,27 @@ fn parse_or_logic() {
     assert_eq!(vars[0], "can add stuff".into());
 }
 
+#[test]
+fn parse_if_next_errors() {
+    let bad = ["if ready next:select {\"A\" a if}",
+               "if ready next:spawn",
+               "if ready next:input player.name",
+               "if ready next:random {a 1 b}",
+               "if ready next:await 10 inn timeout",
+               "if ready next:select 5 {\"A\" a}",
+               "or \"Later\" next:teleport home"];
+    for stmt in bad.iter() {
+        let src = format!("root\n    ready 1 < 2\n    {}\n;", stmt);
+        assert!(Parser::parse_blocks(&src).is_err(), "{} should not parse", stmt);
+    }
+
+    let src = "root\n    ready 1 < 2\n    if ready \"Go\" next:spawn chatter\n;\nchatter\n    emit \"Hi\"\n;";
+    let mut env = Parser::parse_blocks(src).expect("ERROR: Unable to parse source").into_env();
+    let mut ev = Evaluator::new(&mut env);
+    let (vars,next) = ev.next().unwrap();
+    assert_eq!(vars, vec!["Go".into()]);
+    assert_eq!(next, Some(Next::Spawn("chatter".to_owned(), "chatter".to_owned())));
+}
 
 #[test]
 fn validate_inv_logic() {
@@ -428,3 +457,146 @@ fn inline_logic_inv() {
     let (vars,_) = ev.next().unwrap();
     assert_eq!(vars[0], 5. .into());
 }
+
+#[test]
+fn obj_nested_paths() {
+    let src = "def party\n
+  members.0.hp 30\n
+  members.1.hp 12\n
+;\n
+
+def daggers\n
+  damage 1.5\n
+  runes.fire 2\n
+;\n
+
+root\n
+  @party.members.1.hp + 8\n
+  emit party.members.0.hp party.members.1.hp\n
+  @player.bag.dagger new daggers\n
+  @player.bag.dagger.runes.fire 3\n
+  emit player.bag.dagger.runes.fire daggers.runes.fire\n
+;\n";
+
+    let mut env = Parser::parse_blocks(src).expect("ERROR: Unable to parse source").into_env();
+
+    let mut ev = Evaluator::new(&mut env);
+    
+    let (vars,_) = ev.next().unwrap();
+    assert_eq!(vars, [30. .into(), 20. .into()]);
+
+    // template maps are deep-copied, so the original is left untouched
+    let (vars,_) = ev.next().unwrap();
+    assert_eq!(vars, [3. .into(), 2. .into()]);
+}
+
+#[test]
+fn format_interpolation() {
+    let src = "root\n
+    has_key exists player.key\n
+    emit \"{player.name}, you have {player.gold:,} gold.\"\n
+    emit \"hp: {player.hp / 3.0:.1} [{player.name:>5}] [{player.gold * 2:<10,}] {{literal}}\"\n
+    emit \"The door is {has_key ? 'open' : 'locked'}, {player.missing}!\"\n
+    emit \"{player.gold > 1000 && !has_key ? 'rich' : 'poor'} {oops +}\"\n
+;\n
+def player\n
+    name \"Io\"\n
+    gold 1234567\n
+    hp 10\n
+;";
+
+    let mut env = Parser::parse_blocks(src).expect("ERROR: Unable to parse source").into_env();
+    let mut ev = Evaluator::new(&mut env);
+
+    let (vars,_) = ev.next().unwrap();
+    assert_eq!(vars[0], "Io, you have 1,234,567 gold.".into());
+
+    let (vars,_) = ev.next().unwrap();
+    assert_eq!(vars[0], "hp: 3.3 [   Io] [2,469,134 ] {literal}".into());
+
+    let (vars,_) = ev.next().unwrap();
+    assert_eq!(vars[0], "The door is locked, {player.missing}!".into());
+
+    let (vars,_) = ev.next().unwrap();
+    assert_eq!(vars[0], "rich {oops +}".into());
+}
+
+#[test]
+fn format_unresolved_as_written() {
+    let src = "root\n
+    emit \"Press {enter} to continue, {player.name}\"\n
+    emit \"The door is {has_key ? 'open' : 'locked'}, {player.title|sir|stranger}\"\n
+    emit \"[{player.name:99999999999}] [{player.gold:.99999999999}] [{player.gold:04}]\"\n
+;\n
+def player\n
+    gold 7\n
+;";
+
+    let mut env = Parser::parse_blocks(src).expect("ERROR: Unable to parse source").into_env();
+    let mut ev = Evaluator::new(&mut env);
+
+    let (vars,_) = ev.next().unwrap();
+    assert_eq!(vars[0], "Press {enter} to continue, {player.name}".into());
+
+    // within an expression or form, unresolved names are nil
+    let (vars,_) = ev.next().unwrap();
+    assert_eq!(vars[0], "The door is locked, stranger".into());
+
+    // oversized specs are refused rather than allocated
+    let (vars,_) = ev.next().unwrap();
+    assert_eq!(vars[0], "[{player.name:99999999999}] [{player.gold:.99999999999}] [0007]".into());
+}
+
+#[test]
+fn format_plural_select() {
+    let src = "root\n
+    emit \"you have {player.coins} {player.coins|coin|coins}\"\n
+    @player.coins 1\n
+    emit \"you have {player.coins} {player.coins|coin|coins}, {player.coins|=0:none|one:a single coin|other:{player.coins} coins}\"\n
+    emit \"{player.gender|male:he|female:she|other:they} waves, {player.title|=0:x|other:untitled}\"\n
+    @player.coins 0\n
+    @player.gender \"female\"\n
+    emit \"{player.coins|=0:no coins|one:a coin|other:{player.coins} coins}, {player.gender|male:he|female:she|other:they}\"\n
+;\n
+def player\n
+    coins 2\n
+    gender \"nonbinary\"\n
+;";
+
+    let mut env = Parser::parse_blocks(src).expect("ERROR: Unable to parse source").into_env();
+    let mut ev = Evaluator::new(&mut env);
+
+    let (vars,_) = ev.next().unwrap();
+    assert_eq!(vars[0], "you have 2 coins".into());
+    let (vars,_) = ev.next().unwrap();
+    assert_eq!(vars[0], "you have 1 coin, a single coin".into());
+    let (vars,_) = ev.next().unwrap();
+    assert_eq!(vars[0], "they waves, untitled".into());
+    let (vars,_) = ev.next().unwrap();
+    assert_eq!(vars[0], "no coins, she".into());
+}
+
+#[test]
+fn format_plural_locale_rules() {
+    let src = "root\n
+    emit \"{n.a} {n.a|yabloko|yabloka|yablok} {n.b} {n.b|yabloko|yabloka|yablok} {n.c} {n.c|yabloko|yabloka|yablok}\"\n
+;\n
+def n\n
+    a 21\n
+    b 3\n
+    c 11\n
+;";
+
+    let mut env = Parser::parse_blocks(src).expect("ERROR: Unable to parse source").into_env();
+    env.plurals.insert("ru".to_owned(), PluralRules::new(vec![Plural::One, Plural::Few, Plural::Many], |n| {
+        let (n10, n100) = (n % 10., n % 100.);
+        if n10 == 1. && n100 != 11. { Plural::One }
+        else if (2. ..=4.).contains(&n10) && !(12. ..=14.).contains(&n100) { Plural::Few }
+        else { Plural::Many }
+    }));
+    env.locale = "ru".to_owned();
+    
+    let mut ev = Evaluator::new(&mut env);
+    let (vars,_) = ev.next().unwrap();
+    assert_eq!(vars[0], "21 yabloko 3 yabloka 11 yablok".into());
+}